@@ -0,0 +1,148 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Client, Error, File, NameTemplate, Result, Timestamp, WrapErr as _, embed_metadata, file_size,
+    template::unique_name,
+};
+
+/// A local mirror of the library, one file and JSON sidecar per image
+pub struct Backup {
+    dir: PathBuf,
+    name: NameTemplate,
+    embed: bool,
+    manifest: Manifest,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Manifest {
+    files: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub create_time: Timestamp,
+    /// No longer listed remotely, the local copy is kept
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub deleted: bool,
+}
+
+impl Backup {
+    // Existing files keep their names if the template changes
+    pub fn open(dir: &Path, name: NameTemplate, embed: bool) -> Result<Self> {
+        let dir_str = dir.display();
+        fs::create_dir_all(dir).wrap_err(format!("Could not create backup directory {dir_str}"))?;
+        let manifest = match fs::read_to_string(dir.join("manifest.json")) {
+            Ok(text) => serde_json::from_str(&text).map_err(|source| Error::Json {
+                message: format!("Could not decode backup manifest in {dir_str}"),
+                text,
+                source,
+                type_name: "backup manifest",
+            })?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(error) => {
+                return Err(error).wrap_err(format!("Could not read backup manifest in {dir_str}"));
+            }
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            name,
+            embed,
+            manifest,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.manifest.files.iter()
+    }
+
+    // Only trusts the manifest while the file is still there
+    pub fn has(&self, file: &File) -> bool {
+        self.manifest
+            .files
+            .get(&file.id)
+            .is_some_and(|entry| self.dir.join(&entry.name).is_file())
+    }
+
+    pub async fn add(&mut self, client: &Client, file: &File) -> Result<u64> {
+        let name = self.name.render(file)?.to_string_lossy().into_owned();
+        // Downloads overwrite, so names must not be shared between images
+        let name = unique_name(&name, &file.id, |name| self.taken(name, &file.id));
+        let path = self.dir.join(&name);
+        let mut size = client.download(file, &path, true).await?;
+        if self.embed {
+            // Fetched again next time, rather than kept without metadata
+            if let Err(error) = embed_metadata(&path, file) {
+                fs::remove_file(&path).ok();
+                return Err(error);
+            }
+            size = file_size(&path)?;
+        }
+        fs::write(
+            self.dir.join(format!("{name}.json")),
+            serde_json::to_string_pretty(file).unwrap(),
+        )
+        .wrap_err(format!("Could not write sidecar for {name}"))?;
+        self.manifest.files.insert(
+            file.id.clone(),
+            Entry {
+                name,
+                create_time: file.create_time.clone(),
+                deleted: false,
+            },
+        );
+        Ok(size)
+    }
+
+    // By another image in the manifest, or a file on disk other
+    // than the one `id` had before
+    fn taken(&self, name: &str, id: &str) -> bool {
+        let ours = self
+            .manifest
+            .files
+            .get(id)
+            .is_some_and(|entry| entry.name == name);
+        let listed = self
+            .manifest
+            .files
+            .iter()
+            .any(|(other, entry)| other != id && entry.name == name);
+        listed
+            || !ours
+                && (self.dir.join(name).exists() || self.dir.join(format!("{name}.json")).exists())
+    }
+
+    // Marks entries that were not `seen` in a complete listing as
+    // deleted, returning the IDs newly marked
+    pub fn mark_deleted(&mut self, seen: &HashSet<String>) -> Vec<String> {
+        let mut deleted = Vec::new();
+        for (id, entry) in self.manifest.files.iter_mut() {
+            let listed = seen.contains(id);
+            if !listed && !entry.deleted {
+                deleted.push(id.clone());
+            }
+            entry.deleted = !listed;
+        }
+        deleted
+    }
+
+    pub fn store(&self) -> Result<()> {
+        // Written aside and renamed, so an interrupted run keeps the old one
+        let path = self.dir.join("manifest.json");
+        let part = self.dir.join("manifest.json.part");
+        fs::write(&part, serde_json::to_string_pretty(&self.manifest).unwrap())
+            .and_then(|()| fs::rename(&part, &path))
+            .wrap_err(format!(
+                "Could not write backup manifest {}",
+                path.display()
+            ))
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde_json::Value;
+
+use crate::ActiveWindow;
+
+const MOZLZ4_MAGIC: &[u8] = b"mozLz40\0";
+// `kCommandUpdateTabNavigation` in Chromium's session service
+const SNSS_UPDATE_TAB_NAVIGATION: u8 = 6;
+
+enum Browser {
+    Firefox(&'static [&'static str]),
+    Chromium(&'static str),
+}
+
+impl Browser {
+    // From the X11 class or Wayland app ID
+    fn from_app(app: &str) -> Option<Self> {
+        Some(match app.to_ascii_lowercase().as_str() {
+            "firefox" | "firefox-esr" | "org.mozilla.firefox" => Browser::Firefox(&[
+                ".mozilla/firefox",
+                "snap/firefox/common/.mozilla/firefox",
+                ".var/app/org.mozilla.firefox/.mozilla/firefox",
+            ]),
+            "librewolf" | "io.gitlab.librewolf-community" => Browser::Firefox(&[
+                ".librewolf",
+                ".var/app/io.gitlab.librewolf-community/.librewolf",
+            ]),
+            "chromium" | "chromium-browser" | "org.chromium.chromium" => {
+                Browser::Chromium("chromium")
+            }
+            "google-chrome" | "google-chrome-stable" => Browser::Chromium("google-chrome"),
+            "brave-browser" => Browser::Chromium("BraveSoftware/Brave-Browser"),
+            "microsoft-edge" => Browser::Chromium("microsoft-edge"),
+            "vivaldi-stable" => Browser::Chromium("vivaldi"),
+            _ => return None,
+        })
+    }
+}
+
+// The URL of the page shown in a browser window, read from the session
+// files browsers keep to restore tabs, rather than from the address bar
+// as `gyazo.perl` did with synthesized keystrokes. Those are written
+// every few seconds, so a page opened just before capturing may be
+// missing, in which case the window title will not match and we give
+// up rather than guess.
+pub fn browser_url(window: &ActiveWindow) -> Option<String> {
+    let browser = Browser::from_app(window.app.as_deref()?)?;
+    // Without a title, any tab would be a guess
+    let title = window.title.as_deref()?;
+    match browser {
+        Browser::Firefox(roots) => {
+            let home = dirs::home_dir()?;
+            let mut sessions: Vec<PathBuf> = roots
+                .iter()
+                .flat_map(|root| subdirs(&home.join(root)))
+                .map(|profile| profile.join("sessionstore-backups/recovery.jsonlz4"))
+                .collect();
+            newest_first(&mut sessions);
+            sessions
+                .iter()
+                .find_map(|path| firefox_url(&read_mozlz4(path)?, title))
+        }
+        Browser::Chromium(dir) => {
+            let mut sessions: Vec<PathBuf> = subdirs(&dirs::config_dir()?.join(dir))
+                .into_iter()
+                .flat_map(|profile| {
+                    let mut files = subdirs_and_files(&profile.join("Sessions"))
+                        .into_iter()
+                        .filter(|path| {
+                            path.file_name()
+                                .is_some_and(|name| name.to_string_lossy().starts_with("Session_"))
+                        })
+                        .collect::<Vec<_>>();
+                    files.push(profile.join("Current Session"));
+                    files
+                })
+                .collect();
+            newest_first(&mut sessions);
+            sessions
+                .iter()
+                .find_map(|path| chromium_url(&fs::read(path).ok()?, title))
+        }
+    }
+}
+
+// "Page — Mozilla Firefox" or "Page - Chromium" shows "Page"
+fn title_matches(window: &str, page: &str) -> bool {
+    !page.is_empty()
+        && (window == page
+            || [" — ", " - ", " – "]
+                .iter()
+                .filter_map(|separator| window.rsplit_once(separator))
+                .any(|(prefix, _)| prefix == page))
+}
+
+fn read_mozlz4(path: &Path) -> Option<Value> {
+    let bytes = fs::read(path).ok()?;
+    let rest = bytes.strip_prefix(MOZLZ4_MAGIC)?;
+    let (size, block) = rest.split_at_checked(4)?;
+    let size = u32::from_le_bytes(size.try_into().ok()?) as usize;
+    let json = lz4_flex::block::decompress(block, size).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+// The current page of the tab whose title the window shows,
+// looking at the selected tabs first
+fn firefox_url(session: &Value, title: &str) -> Option<String> {
+    // Indices in the session are counted from 1
+    let nth = |array: &Value, index: &Value| {
+        let index = index.as_u64()?.checked_sub(1)? as usize;
+        array.as_array()?.get(index).cloned()
+    };
+    let current = |tab: &Value| nth(&tab["entries"], &tab["index"]);
+    let windows = session["windows"].as_array()?;
+    let selected = nth(&session["windows"], &session["selectedWindow"])
+        .and_then(|window| nth(&window["tabs"], &window["selected"]));
+
+    let selected_tabs = windows
+        .iter()
+        .filter_map(|window| nth(&window["tabs"], &window["selected"]));
+    let all_tabs = windows
+        .iter()
+        .filter_map(|window| window["tabs"].as_array())
+        .flatten()
+        .cloned();
+    selected
+        .into_iter()
+        .chain(selected_tabs)
+        .chain(all_tabs)
+        .filter_map(|tab| current(&tab))
+        .find(|entry| {
+            entry["title"]
+                .as_str()
+                .is_some_and(|page| title_matches(title, page))
+        })
+        .and_then(|entry| entry["url"].as_str().map(str::to_string))
+}
+
+// The URL of the latest navigation to a page with the window's title,
+// from the records of an SNSS session file
+fn chromium_url(bytes: &[u8], title: &str) -> Option<String> {
+    let mut rest = bytes.strip_prefix(b"SNSS")?.get(4..)?;
+    let mut url = None;
+    while let Some((size, tail)) = rest.split_at_checked(2) {
+        let size = u16::from_le_bytes([size[0], size[1]]) as usize;
+        let (record, tail) = tail.split_at_checked(size)?;
+        rest = tail;
+        if let Some((&SNSS_UPDATE_TAB_NAVIGATION, payload)) = record.split_first()
+            && let Some((page_url, page_title)) = navigation(payload)
+            && title_matches(title, &page_title)
+        {
+            url = Some(page_url);
+        }
+    }
+    url
+}
+
+// A pickled navigation: the pickle's size, tab ID, index, URL and title
+fn navigation(payload: &[u8]) -> Option<(String, String)> {
+    let mut pickle = Pickle(payload.get(4..)?);
+    let _tab_id = pickle.int()?;
+    let _index = pickle.int()?;
+    let url = pickle.string()?;
+    let title = pickle.string16()?;
+    Some((url, title))
+}
+
+struct Pickle<'a>(&'a [u8]);
+
+impl Pickle<'_> {
+    // Fields are padded to 4 bytes
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let padded = len.checked_add(3)? & !3;
+        let bytes = self.0.get(..len)?;
+        self.0 = self.0.get(padded..).unwrap_or_default();
+        Some(bytes)
+    }
+
+    fn int(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = usize::try_from(self.int()?).ok()?;
+        Some(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn string16(&mut self) -> Option<String> {
+        let len = usize::try_from(self.int()?).ok()?;
+        let units: Vec<u16> = self
+            .take(len.checked_mul(2)?)?
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    subdirs_and_files(dir)
+        .into_iter()
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn subdirs_and_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect()
+}
+
+// Drops paths that do not exist
+fn newest_first(paths: &mut Vec<PathBuf>) {
+    let modified = |path: &PathBuf| path.metadata().and_then(|meta| meta.modified()).ok();
+    paths.retain(|path| modified(path).is_some());
+    paths.sort_by_key(|path| core::cmp::Reverse(modified(path).unwrap_or(SystemTime::UNIX_EPOCH)));
+}
@@ -0,0 +1,452 @@
+use std::{env, fmt, path::Path, process::Command};
+
+use tempfile::NamedTempFile;
+
+use image::ImageFormat;
+
+use crate::{
+    Error, FileKind, Region, Result, WrapErr as _, command,
+    window::{active_window_id, focused_region, screen_region},
+};
+
+/// The kind of graphical session we run in, which decides the tools that work
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Session {
+    X11,
+    Wayland,
+    Unknown,
+}
+
+impl Session {
+    pub fn detect() -> Self {
+        let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+        match var("XDG_SESSION_TYPE").as_deref() {
+            Some("wayland") => return Session::Wayland,
+            Some("x11") => return Session::X11,
+            _ => {}
+        }
+        // XWayland sets `DISPLAY` too, so check Wayland first
+        if var("WAYLAND_DISPLAY").is_some() {
+            Session::Wayland
+        } else if var("DISPLAY").is_some() {
+            Session::X11
+        } else {
+            Session::Unknown
+        }
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Session::X11 => "X11",
+            Session::Wayland => "Wayland",
+            Session::Unknown => "unknown",
+        })
+    }
+}
+
+/// What to capture
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CaptureMode {
+    /// A region the user selects
+    #[default]
+    Select,
+    Fullscreen,
+    /// The focused window
+    Window,
+    Region(Region),
+    /// A single monitor, numbered from 0
+    Screen(usize),
+}
+
+/// A screenshot, recording or pasted image in a temporary file, removed when dropped
+pub struct Captured {
+    pub file: NamedTempFile,
+    pub size: u64,
+}
+
+impl Captured {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// An external screenshot tool
+pub trait CaptureBackend: Send + Sync {
+    /// As set in the `capture.backend` config
+    fn name(&self) -> &'static str;
+
+    /// The executables it runs
+    fn programs(&self) -> &'static [&'static str];
+
+    fn supports(&self, session: Session) -> bool;
+
+    /// Captures into `path` as PNG. Never given `Screen`, which is
+    /// captured as the region of that screen.
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()>;
+
+    fn is_available(&self, session: Session) -> bool {
+        self.supports(session)
+            && self
+                .programs()
+                .iter()
+                .all(|program| command::exists(program))
+    }
+
+    // The same checks for every tool, as some exit successfully
+    // without writing anything when the selection is cancelled
+    fn capture(&self, mode: CaptureMode) -> Result<Captured> {
+        let mode = match mode {
+            CaptureMode::Screen(n) => CaptureMode::Region(screen_region(n, Session::detect())?),
+            mode => mode,
+        };
+        let file = NamedTempFile::with_suffix(".png")
+            .wrap_err("Could not create a temporary file for the capture")?;
+        self.capture_to(file.path(), mode)?;
+        let size = crate::file_size(file.path())?;
+        if size == 0 {
+            return Err(Error::Missing {
+                message: format!("Nothing was captured with {}", self.name()),
+            });
+        }
+        let kind = FileKind::detect(file.path())?;
+        if kind != FileKind::Png {
+            return Err(Error::Unsupported {
+                message: format!("{} wrote a {kind} file instead of PNG", self.name()),
+            });
+        }
+        Ok(Captured { file, size })
+    }
+}
+
+// Tools that only work on X11 would capture a black screen or
+// only XWayland windows on Wayland
+fn x11(session: Session) -> bool {
+    session != Session::Wayland
+}
+
+// Tools for wlroots compositors such as Sway, as GNOME and KDE
+// do not implement the screencopy protocol they use
+pub(crate) fn wlroots(session: Session) -> bool {
+    session == Session::Wayland && !is_desktop("GNOME") && !is_desktop("KDE")
+}
+
+// Whether `XDG_CURRENT_DESKTOP` lists the desktop, such as "ubuntu:GNOME"
+fn is_desktop(name: &str) -> bool {
+    env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktops| {
+        desktops
+            .split(':')
+            .any(|desktop| desktop.eq_ignore_ascii_case(name))
+    })
+}
+
+fn run(backend: &dyn CaptureBackend, command: &mut Command) -> Result<()> {
+    command::run(
+        command,
+        format!("Could not capture a screenshot with {}", backend.name()),
+    )
+    .map(drop)
+}
+
+// For tools that cannot capture a given region themselves
+fn crop(path: &Path, region: Region) -> Result<()> {
+    let path_str = path.display().to_string();
+    let image = image::open(path).wrap_err(format!("Could not decode capture {path_str}"))?;
+    let (x, y) = (region.x.max(0) as u32, region.y.max(0) as u32);
+    if x >= image.width() || y >= image.height() {
+        return Err(Error::String(format!(
+            "Region {region} is outside the {}x{} screen",
+            image.width(),
+            image.height()
+        )));
+    }
+    image
+        .crop_imm(x, y, region.width, region.height)
+        .save_with_format(path, ImageFormat::Png)
+        .wrap_err(format!("Could not write cropped capture {path_str}"))
+}
+
+/// ImageMagick's `import`
+pub struct Import;
+
+impl CaptureBackend for Import {
+    fn name(&self) -> &'static str {
+        "import"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["import"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        x11(session)
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        let mut import = Command::new("import");
+        match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => &mut import,
+            CaptureMode::Fullscreen => import.args(["-window", "root"]),
+            CaptureMode::Window => import.arg("-window").arg(active_window_id()?),
+            CaptureMode::Region(region) => import
+                .args(["-window", "root", "-crop"])
+                .arg(region.geometry())
+                .arg("+repage"),
+        };
+        run(self, import.arg(path))
+    }
+}
+
+pub struct Maim;
+
+impl CaptureBackend for Maim {
+    fn name(&self) -> &'static str {
+        "maim"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["maim"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        x11(session)
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        let mut maim = Command::new("maim");
+        maim.args(["--format", "png"]);
+        match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => maim.arg("--select"),
+            CaptureMode::Fullscreen => &mut maim,
+            CaptureMode::Window => maim.arg("--window").arg(active_window_id()?),
+            CaptureMode::Region(region) => maim.arg("--geometry").arg(region.geometry()),
+        };
+        run(self, maim.arg(path))
+    }
+}
+
+pub struct Scrot;
+
+impl CaptureBackend for Scrot {
+    fn name(&self) -> &'static str {
+        "scrot"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["scrot"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        x11(session)
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        // Without `--overwrite` it picks another name as the file exists
+        let mut scrot = Command::new("scrot");
+        scrot.arg("--overwrite");
+        match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => scrot.arg("--select"),
+            CaptureMode::Fullscreen => &mut scrot,
+            CaptureMode::Window => scrot.arg("--focused"),
+            CaptureMode::Region(Region {
+                x,
+                y,
+                width,
+                height,
+            }) => scrot
+                .arg("--autoselect")
+                .arg(format!("{x},{y},{width},{height}")),
+        };
+        run(self, scrot.arg(path))
+    }
+}
+
+/// `grim` with the region from `slurp`, for wlroots compositors such as Sway
+pub struct Grim;
+
+impl CaptureBackend for Grim {
+    fn name(&self) -> &'static str {
+        "grim"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["grim", "slurp"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        wlroots(session)
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        // It takes regions as `X,Y WxH`, which is what `slurp` prints
+        let geometry = |Region {
+                            x,
+                            y,
+                            width,
+                            height,
+                        }| format!("{x},{y} {width}x{height}");
+        let region = match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => {
+                let region = command::run(
+                    &mut Command::new("slurp"),
+                    "Could not select a region with slurp",
+                )?;
+                Some(String::from_utf8_lossy(&region).trim().to_string())
+            }
+            CaptureMode::Fullscreen => None,
+            CaptureMode::Window => Some(geometry(focused_region()?)),
+            CaptureMode::Region(region) => Some(geometry(region)),
+        };
+        let mut grim = Command::new("grim");
+        grim.args(["-t", "png"]);
+        if let Some(region) = region {
+            grim.arg("-g").arg(region);
+        }
+        run(self, grim.arg(path))
+    }
+}
+
+pub struct GnomeScreenshot;
+
+impl CaptureBackend for GnomeScreenshot {
+    fn name(&self) -> &'static str {
+        "gnome-screenshot"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["gnome-screenshot"]
+    }
+
+    fn supports(&self, _: Session) -> bool {
+        true
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        let mut gnome_screenshot = Command::new("gnome-screenshot");
+        match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => gnome_screenshot.arg("--area"),
+            CaptureMode::Fullscreen | CaptureMode::Region(_) => &mut gnome_screenshot,
+            CaptureMode::Window => gnome_screenshot.arg("--window"),
+        };
+        run(self, gnome_screenshot.arg("--file").arg(path))?;
+        match mode {
+            CaptureMode::Region(region) => crop(path, region),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// KDE's screenshot tool
+pub struct Spectacle;
+
+impl CaptureBackend for Spectacle {
+    fn name(&self) -> &'static str {
+        "spectacle"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["spectacle"]
+    }
+
+    fn supports(&self, _: Session) -> bool {
+        true
+    }
+
+    fn capture_to(&self, path: &Path, mode: CaptureMode) -> Result<()> {
+        // In the background, without notification
+        let mut spectacle = Command::new("spectacle");
+        spectacle.args(["-b", "-n"]);
+        match mode {
+            CaptureMode::Select | CaptureMode::Screen(_) => spectacle.arg("-r"),
+            CaptureMode::Fullscreen | CaptureMode::Region(_) => spectacle.arg("-f"),
+            CaptureMode::Window => spectacle.arg("-a"),
+        };
+        run(self, spectacle.arg("-o").arg(path))?;
+        match mode {
+            CaptureMode::Region(region) => crop(path, region),
+            _ => Ok(()),
+        }
+    }
+}
+
+// In order of preference when detecting
+pub fn capture_backends() -> Vec<Box<dyn CaptureBackend>> {
+    vec![
+        Box::new(Grim),
+        Box::new(Import),
+        Box::new(Maim),
+        Box::new(Scrot),
+        Box::new(GnomeScreenshot),
+        Box::new(Spectacle),
+    ]
+}
+
+// The named backend, or the first one available in this session
+pub fn capture_backend(name: Option<&str>) -> Result<Box<dyn CaptureBackend>> {
+    find_backend(capture_backends(), name, "capture", "screenshot tool")
+}
+
+// What `find_backend` needs of capture and record backends alike
+pub(crate) trait Backend {
+    fn name(&self) -> &'static str;
+    fn programs(&self) -> &'static [&'static str];
+    fn is_available(&self, session: Session) -> bool;
+}
+
+impl Backend for dyn CaptureBackend {
+    fn name(&self) -> &'static str {
+        CaptureBackend::name(self)
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        CaptureBackend::programs(self)
+    }
+
+    fn is_available(&self, session: Session) -> bool {
+        CaptureBackend::is_available(self, session)
+    }
+}
+
+// The backend called `name` if its programs are installed, or else
+// the first of `backends` available in this session, which come
+// in order of preference. `kind` and `tool` name them in errors.
+pub(crate) fn find_backend<B: Backend + ?Sized>(
+    backends: Vec<Box<B>>,
+    name: Option<&str>,
+    kind: &str,
+    tool: &str,
+) -> Result<Box<B>> {
+    let names = backends
+        .iter()
+        .map(|backend| backend.name())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let session = Session::detect();
+    if let Some(name) = name {
+        let backend = backends
+            .into_iter()
+            .find(|backend| backend.name() == name)
+            .ok_or_else(|| Error::Unsupported {
+                message: format!("Unknown {kind} backend `{name}`, expected one of {names}"),
+            })?;
+        if let Some(program) = backend
+            .programs()
+            .iter()
+            .find(|program| !command::exists(program))
+        {
+            return Err(Error::Missing {
+                message: format!(
+                    "The {kind} backend {name} needs `{program}`, which is not installed"
+                ),
+            });
+        }
+        return Ok(backend);
+    }
+    backends
+        .into_iter()
+        .find(|backend| backend.is_available(session))
+        .ok_or_else(|| Error::Missing {
+            message: format!("No {tool} found for this {session} session, install one of {names}"),
+        })
+}
@@ -1,11 +1,20 @@
-use std::{path::PathBuf, pin::pin, process};
+use std::{
+    io::{BufRead as _, IsTerminal as _},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::Arc,
+};
 
 use clap::{Args, Parser, Subcommand};
-use color_eyre::eyre::{Result, WrapErr as _};
+use color_eyre::{
+    Report,
+    eyre::{Result, WrapErr as _, bail},
+};
 use futures_util::StreamExt as _;
+use indicatif::{ProgressBar, ProgressStyle};
 use tokio::fs;
 
-use crate::Client;
+use crate::{Client, ImageId};
 
 fn compact<T: serde::Serialize>(value: &T) -> String {
     serde_json::to_string(value).unwrap()
@@ -15,6 +24,111 @@ fn pretty<T: serde::Serialize>(value: &T) -> String {
     serde_json::to_string_pretty(value).unwrap()
 }
 
+// Shows upload progress on stderr, unless it is not a terminal
+fn progress_bar(upload: &mut crate::Upload) -> ProgressBar {
+    let bar = if std::io::stderr().is_terminal() {
+        ProgressBar::new(0).with_style(
+            ProgressStyle::with_template(
+                "{bar:40} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec})",
+            )
+            .unwrap(),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    let progress = bar.clone();
+    upload.progress = Some(Arc::new(move |sent, total| {
+        progress.set_length(total);
+        progress.set_position(sent);
+    }));
+    bar
+}
+
+// The error and all its causes, for output that is not a report
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        chain.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+    chain
+}
+
+// Keeps captures that failed to upload for a reason that may pass,
+// as their temporary file is about to be deleted
+fn queue_failed(path: &Path, upload: &crate::Upload, error: crate::Error, what: &str) -> Report {
+    if error.is_transient()
+        && let Ok(queued) = crate::Queue::push(path, upload, &error)
+    {
+        let id = queued.id;
+        return Report::new(error).wrap_err(format!(
+            "Failed to upload {what}, queued it as {id} for `gyazo queue flush`"
+        ));
+    }
+    Report::new(error).wrap_err(format!("Failed to upload {what}"))
+}
+
+// Notifications for commands run from hotkeys, whose output no one sees
+fn notifier(wanted: impl FnOnce(&crate::config::Notify) -> bool) -> Option<crate::Notifier> {
+    use crate::config::NotifyWhen;
+
+    let config = crate::Config::load().notify;
+    let when = match config.when {
+        NotifyWhen::Auto => !std::io::stderr().is_terminal(),
+        NotifyWhen::Always => true,
+        NotifyWhen::Never => false,
+    };
+    if !when || !wanted(&config) {
+        return None;
+    }
+    crate::Notifier::new(std::time::Duration::from_secs(config.timeout))
+        .inspect_err(|error| eprintln!("Failed to notify: {}", error_chain(error)))
+        .ok()
+}
+
+// On a blocking thread, as D-Bus calls block and clicks are waited for
+async fn notify_uploaded(uploaded: &crate::Uploaded, path: &Path) {
+    let (url, path) = (uploaded.url(), path.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        if let Some(notifier) = notifier(|config| config.uploads)
+            && let Err(error) = notifier.uploaded(&url, &path)
+        {
+            eprintln!("Failed to notify: {}", error_chain(&error));
+        }
+    })
+    .await
+    .ok();
+}
+
+async fn notify_error<T>(result: Result<T>) -> Result<T> {
+    if let Err(report) = &result {
+        let message = format!("{report:#}");
+        tokio::task::spawn_blocking(move || {
+            if let Some(notifier) = notifier(|config| config.errors)
+                && let Err(error) = notifier.error(&message)
+            {
+                eprintln!("Failed to notify: {}", error_chain(&error));
+            }
+        })
+        .await
+        .ok();
+    }
+    result
+}
+
+// Non-empty lines, trimmed
+fn stdin_lines() -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for line in std::io::stdin().lock().lines() {
+        let line = line.wrap_err("Failed to read from stdin")?;
+        if !line.trim().is_empty() {
+            lines.push(line.trim().to_string());
+        }
+    }
+    Ok(lines)
+}
+
 fn json_string<T: serde::Serialize>(value: &T, pretty_: bool) -> String {
     if pretty_ {
         pretty(value)
@@ -72,7 +186,10 @@ pub enum Command {
     // Gui(crate::Gui),
     Image(Image),
     Video(Video),
+    Backup(Backup),
     Count(Count),
+    Export(Export),
+    Hash(Hash),
     #[clap(aliases = ["down", "dl"])]
     Download(Download),
     #[clap(aliases = ["file", "info"])]
@@ -87,6 +204,7 @@ pub enum Command {
     #[clap(hide = true)]
     ConfigPath,
     Me(Me),
+    Queue(Queue),
 }
 
 impl Gyazo {
@@ -119,13 +237,16 @@ impl Gyazo {
 
         match self.command {
             // Gui(cmd) => cmd.run().unwrap(),
-            Image(cmd) => cmd.run(client).await?,
+            Image(cmd) => notify_error(cmd.run(client).await).await?,
+            Backup(cmd) => cmd.run(client).await?,
             Count(cmd) => cmd.run(client).await?,
+            Export(cmd) => cmd.run(client).await?,
+            Hash(cmd) => cmd.run()?,
             Download(cmd) => cmd.run(client).await?,
             Get(cmd) => cmd.run(client).await?,
             List(cmd) => cmd.run(client).await?,
-            Video(cmd) => cmd.run(client).await?,
-            Upload(cmd) => cmd.run(client).await?,
+            Video(cmd) => notify_error(cmd.run(client).await).await?,
+            Upload(cmd) => notify_error(cmd.run(client).await).await?,
             Config => {
                 let config = toml::to_string_pretty(&config)
                     .wrap_err("Failed to serialize config file as TOML")?;
@@ -134,6 +255,7 @@ impl Gyazo {
             ConfigDir => println!("{}", Config::dir().display()),
             ConfigPath => println!("{}", Config::path().display()),
             Me(cmd) => cmd.run(client).await?,
+            Queue(cmd) => cmd.run(client).await?,
         }
         Ok(())
     }
@@ -158,6 +280,12 @@ pub struct UploadArgs {
         alias = "public-meta"
     )]
     pub private_metadata: bool,
+    /// Format to convert unaccepted images to (png, jpeg or gif)
+    #[clap(long, value_parser = parse_image_target)]
+    pub convert_to: Option<crate::FileKind>,
+    /// Upload even if the same file was uploaded before
+    #[clap(action, long)]
+    pub no_dedup: bool,
 }
 
 #[derive(Args, Clone, Debug)]
@@ -166,6 +294,41 @@ pub struct Open {
     pub open: bool,
 }
 
+#[derive(Args, Clone, Debug)]
+pub struct CopyArgs {
+    /// Copy the link, direct URL, Markdown, HTML or image to the clipboard
+    #[clap(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "link",
+        value_name = "TARGET"
+    )]
+    pub copy: Option<crate::CopyTarget>,
+    /// Do not copy, even if the config sets `upload.copy`
+    #[clap(action, conflicts_with = "copy", long)]
+    pub no_copy: bool,
+}
+
+impl CopyArgs {
+    fn target(&self) -> Option<crate::CopyTarget> {
+        if self.no_copy {
+            return None;
+        }
+        self.copy.or(crate::Config::load().upload.copy)
+    }
+
+    // Copies a single upload, from the local file if copying the image
+    fn copy(&self, uploaded: &crate::Uploaded, path: &Path) -> Result<()> {
+        if let Some(target) = self.target() {
+            let copied = crate::copy_upload(crate::Session::detect(), target, uploaded, path)
+                .wrap_err("Failed to copy to the clipboard")?;
+            println!("Copied {copied} to the clipboard");
+        }
+        Ok(())
+    }
+}
+
 impl UploadArgs {
     pub fn update(&self, mut upload: crate::Upload) -> crate::Upload {
         if let Some(app) = self.app.as_ref() {
@@ -186,6 +349,12 @@ impl UploadArgs {
         if self.anonymous {
             upload.anonymous = true;
         }
+        if let Some(convert_to) = self.convert_to {
+            upload.convert_to = convert_to;
+        }
+        if self.no_dedup {
+            upload.dedup = false;
+        }
         upload
     }
 }
@@ -196,36 +365,85 @@ pub struct Image {
     upload: UploadArgs,
     #[clap(flatten)]
     open: Open,
+    #[clap(flatten)]
+    copy: CopyArgs,
+    /// Screenshot tool, eg. maim or grim, instead of the configured or detected one
+    #[clap(long)]
+    pub backend: Option<String>,
+    /// Capture every screen
+    #[clap(action, long, group = "mode")]
+    pub fullscreen: bool,
+    /// Capture the focused window
+    #[clap(action, long, group = "mode")]
+    pub window: bool,
+    /// Capture this region without selecting it, eg. 0,0,1280x720
+    #[clap(long, group = "mode", value_name = "X,Y,WxH")]
+    pub region: Option<crate::Region>,
+    /// Capture one screen, numbered from 0 as by `xrandr --listmonitors`
+    #[clap(long, group = "mode", value_name = "N")]
+    pub screen: Option<usize>,
+    /// Seconds to wait before capturing
+    #[clap(long, default_value_t = 0.0, value_name = "SECS")]
+    pub delay: f64,
 }
 
 impl Image {
     pub async fn run(self, client: &Client) -> Result<()> {
-        let file = tempfile::NamedTempFile::with_suffix(".png")
-            .wrap_err("Failed to create a temporary PNG file")?;
-        let path = file.path().to_str().unwrap();
-        println!("Select the region to capture");
-        let _ = process::Command::new("import")
-            .args(&[path])
-            .output()
-            .wrap_err("Failed to run ImageMagick `import` command to capture an image")?;
-
-        let size = file
-            .path()
-            .metadata()
-            .wrap_err("Failed to determine size of captured image")?
-            .len();
-        println!("Uploading {size} bytes");
-        let upload = self.upload.update(crate::Upload::default());
-        let url = client
-            .upload_image(file.path(), &upload)
-            .await
-            .wrap_err("Failed to upload captured image")?;
+        let config = crate::Config::load().capture;
+        let backend = self.backend.clone().or(config.backend);
+        let backend = crate::capture_backend(backend.as_deref())
+            .wrap_err("Failed to find a screenshot tool")?;
+        let delay = std::time::Duration::try_from_secs_f64(self.delay)
+            .wrap_err_with(|| format!("Invalid delay of {} seconds", self.delay))?;
+        tokio::time::sleep(delay).await;
+        // After the delay, which is there to switch windows, and
+        // before selecting, which may move the focus
+        let window = config
+            .window_metadata
+            .then(|| crate::active_window(crate::Session::detect()).ok())
+            .flatten();
+        let mode = self.mode();
+        if mode == crate::CaptureMode::Select {
+            println!("Select the region to capture");
+        }
+        let captured = backend
+            .capture(mode)
+            .wrap_err_with(|| format!("Failed to capture an image with {}", backend.name()))?;
+
+        println!("Uploading {} bytes", captured.size);
+        let mut upload = crate::Upload::default();
+        if let Some(window) = window {
+            upload.url = crate::browser_url(&window);
+            upload.app = window.app.unwrap_or(upload.app);
+            upload.title = window.title;
+        }
+        let mut upload = self.upload.update(upload);
+        let bar = progress_bar(&mut upload);
+        let uploaded = client.upload(captured.path(), &upload).await;
+        bar.finish_and_clear();
+        let uploaded = uploaded
+            .map_err(|error| queue_failed(captured.path(), &upload, error, "captured image"))?;
+        let url = uploaded.url();
         println!("URL: {url}");
+        self.copy.copy(&uploaded, captured.path())?;
         if self.open.open {
             open::that(url.as_str()).wrap_err("Failed to open URL in browser")?;
         }
+        notify_uploaded(&uploaded, captured.path()).await;
         Ok(())
     }
+
+    fn mode(&self) -> crate::CaptureMode {
+        use crate::CaptureMode::*;
+
+        match (self.fullscreen, self.window, self.region, self.screen) {
+            (true, ..) => Fullscreen,
+            (_, true, ..) => Window,
+            (_, _, Some(region), _) => Region(region),
+            (.., Some(n)) => Screen(n),
+            _ => Select,
+        }
+    }
 }
 
 struct VideoName;
@@ -252,93 +470,50 @@ pub struct Video {
     pub seconds: u64,
     #[clap(flatten)]
     open: Open,
+    #[clap(flatten)]
+    copy: CopyArgs,
     #[clap(long, default_missing_value = VideoName, num_args=0..=1)]
     save: Option<PathBuf>,
+    /// Screen recorder, eg. ffmpeg or wf-recorder, instead of the configured or detected one
+    #[clap(long)]
+    pub backend: Option<String>,
 }
 
 impl Video {
     pub async fn run(self, client: &Client) -> Result<()> {
-        // select
-        println!("Select the region to record");
-        let output = process::Command::new("slop")
-            .args(&["-f", ":0.0+%x,%y %wx%h"])
-            .output()
-            .wrap_err("Failed to select rectangle to record video within")?;
-        let mut it = std::str::from_utf8(&output.stdout)
-            .wrap_err("`slop` output was not UTF8")?
-            .split(' ');
-        let xy = it.next().unwrap();
-        let wh = it.next().unwrap();
-
-        // the encoder needs height+width divisible by two
-        println!("wh before: {wh}");
-        let (w, h) = wh.split_once('x').unwrap();
-        let w = (w.parse::<u32>().unwrap() / 2) * 2;
-        let h = (h.parse::<u32>().unwrap() / 2) * 2;
-        let wh = format!("{w}x{h}");
-        println!("wh after: {wh}");
-
-        // record
-        let file = tempfile::NamedTempFile::with_suffix(".mp4")
-            .wrap_err("Failed to create a temporary MP4 file")?;
-        let path = file.path().to_str().unwrap();
-        println!("path: {path}");
-        let seconds = self.seconds.to_string();
-        let args = &[
-            "-f",
-            "x11grab",
-            "-s",
-            wh.as_str(),
-            "-i",
-            xy,
-            "-f",
-            "alsa",
-            "-i",
-            "pulse",
-            "-t",
-            seconds.as_str(),
-            // without this, videos won't play on Windows
-            "-pix_fmt",
-            "yuv420p",
-            "-y",
-            // uhh.. Stream #0: not enough frames to estimate rate; consider increasing probesize
-            "-framerate",
-            "24",
-            "-probesize",
-            "64MB",
-            path,
-        ];
-        println!("ffmpeg: {}", args.join(" "));
-        println!("Recording for {seconds} seconds");
-        process::Command::new("ffmpeg")
-            .args(args)
-            .output()
-            .wrap_err("Failed to run FFmpeg to record a video")?;
-        let size = file
-            .path()
-            .metadata()
-            .wrap_err("Failed to determine size of recorded video")?
-            .len();
+        let config = crate::Config::load().capture;
+        let backend = self.backend.clone().or(config.recorder);
+        let backend = crate::record_backend(backend.as_deref())
+            .wrap_err("Failed to find a screen recorder")?;
+        println!("Select what to record");
+        println!("Recording for {} seconds", self.seconds);
+        let (name, duration) = (backend.name(), std::time::Duration::from_secs(self.seconds));
+        // Recorders and the portal block until done
+        let recorded = tokio::task::spawn_blocking(move || backend.record(duration))
+            .await
+            .wrap_err("Failed to wait for the recording")?
+            .wrap_err_with(|| format!("Failed to record a video with {name}"))?;
 
         if let Some(path) = self.save.as_ref() {
-            // use md5::Digest as _;
-            // let bytes = fs::read(file.path()).await.unwrap();
-            // let digest = hex::encode(md5::Md5::digest(&bytes));
-            // println!("hex: {digest}");
             println!("Saving to {}", path.display());
-            fs::copy(file.path(), path).await.unwrap();
+            fs::copy(recorded.path(), path).await.unwrap();
         }
 
         // upload
-        println!("Uploading {size} bytes");
-        let url = client
-            .upload_video(file.path())
-            .await
-            .wrap_err("Failed to upload recorded video")?;
+        println!("Uploading {} bytes", recorded.size);
+        let mut upload = crate::Upload::default();
+        let bar = progress_bar(&mut upload);
+        let uploaded = client.upload(recorded.path(), &upload).await;
+        bar.finish_and_clear();
+        let uploaded = uploaded
+            .map_err(|error| queue_failed(recorded.path(), &upload, error, "recorded video"))?;
+        let url = uploaded.url();
         println!("URL: {url}");
+        self.copy.copy(&uploaded, recorded.path())?;
         if self.open.open {
             open::that(url.as_str()).wrap_err("Failed to open URL in browser")?;
         }
+        notify_uploaded(&uploaded, recorded.path()).await;
         Ok(())
     }
 }
@@ -357,6 +532,37 @@ impl Count {
     }
 }
 
+#[derive(Args, Debug)]
+pub struct Hash {
+    /// Hash videos other than MP4 as they are, not as they would be transcoded
+    #[clap(action, long)]
+    pub no_transcode: bool,
+    pub file: PathBuf,
+}
+
+impl Hash {
+    // The ID the file would be uploaded as, after any conversion,
+    // and what the upload is also remembered by if it differs
+    pub fn run(self) -> Result<()> {
+        let upload = crate::Upload {
+            transcode: !self.no_transcode,
+            ..Default::default()
+        };
+        let file_str = self.file.display().to_string();
+        let prepared = crate::prepare(&self.file, &upload)
+            .wrap_err_with(|| format!("Failed to prepare {file_str} for upload"))?;
+        let id = crate::content_id(prepared.path())
+            .wrap_err_with(|| format!("Failed to hash {file_str}"))?;
+        println!("{id}");
+        if prepared.is_converted() {
+            let source = crate::content_id(&self.file)
+                .wrap_err_with(|| format!("Failed to hash {file_str}"))?;
+            println!("Source: {source}");
+        }
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct Me;
 
@@ -373,7 +579,8 @@ impl Me {
 
 #[derive(Args, Debug)]
 pub struct Get {
-    id: String,
+    /// Image ID, permalink or direct URL
+    id: ImageId,
 }
 
 impl Get {
@@ -393,6 +600,12 @@ pub struct List {
     pub internal: bool,
     #[clap(action, long)]
     pub pretty: bool,
+    /// Skip checking which "gif" files are really MP4 videos
+    #[clap(action, long)]
+    pub no_detect_mp4: bool,
+    /// How many of those checks to run at once
+    #[clap(long, short, default_value_t = 8)]
+    pub jobs: usize,
 }
 
 impl List {
@@ -405,7 +618,11 @@ impl List {
                 println!("{}", json_string(&file, self.pretty));
             }
         } else {
-            let mut files = pin!(client.list().await);
+            let options = crate::ListOptions {
+                detect_mp4: !self.no_detect_mp4,
+                jobs: self.jobs,
+            };
+            let mut files = pin!(client.list_with(options).await);
             while let Some(file) = files.next().await {
                 let file = file.wrap_err("Failed to determine file information with API")?;
                 println!("{}", json_string(&file, self.pretty));
@@ -417,88 +634,646 @@ impl List {
 
 #[derive(Args, Debug)]
 pub struct Download {
+    /// File to download a single ID to, or directory for several,
+    /// or `-` to write a single file to stdout
     #[clap(long)]
     pub to: Option<PathBuf>,
     #[clap(action, long, short)]
     pub force: bool,
-    pub id: String,
+    /// Also read IDs from stdin, one per line, such as from `gyazo list`
+    #[clap(action, long)]
+    pub stdin: bool,
+    /// How many files to download at once
+    #[clap(long, short, default_value_t = 4)]
+    pub jobs: usize,
+    /// File name template, eg. "{create_time:%Y-%m-%d} {title}.{type}"
+    #[clap(long)]
+    pub name: Option<crate::NameTemplate>,
+    /// Write title, description, source URL and OCR text into the files
+    #[clap(action, long)]
+    pub embed: bool,
+    /// Image IDs, permalinks or direct URLs
+    #[clap(required_unless_present = "stdin")]
+    pub ids: Vec<ImageId>,
 }
 
 impl Download {
     pub async fn run(self, client: &Client) -> Result<()> {
-        use futures_util::StreamExt as _;
+        let mut ids = self.ids.clone();
+        // Bad lines are reported and skipped, failing only at the end
+        let mut invalid = 0;
+        if self.stdin {
+            for line in stdin_lines()? {
+                // `gyazo list` prints whole files as JSON
+                let id = match serde_json::from_str::<crate::File>(&line) {
+                    Ok(file) => file.id,
+                    Err(_) => line.clone(),
+                };
+                match id.parse() {
+                    Ok(id) => ids.push(id),
+                    Err(error) => {
+                        invalid += 1;
+                        eprintln!("{line} Error: {}", error_chain(&error));
+                    }
+                }
+            }
+        }
+
+        if self.to_stdout() {
+            let [id] = ids.as_slice() else {
+                bail!(
+                    "Can only download a single file to stdout, got {}",
+                    ids.len()
+                );
+            };
+            self.run_one(client, id).await?;
+            if invalid > 0 {
+                bail!("Skipped {invalid} invalid lines from stdin");
+            }
+            return Ok(());
+        }
+        if let [id] = ids.as_slice()
+            && !self.stdin
+        {
+            return self.run_one(client, id).await;
+        }
 
+        let dir = self.to.clone().unwrap_or_default();
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(&dir)
+                .await
+                .wrap_err_with(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        let total = ids.len() + invalid;
+        let mut failed = invalid;
+        let (name, force, embed) = (&self.name(), self.force, self.embed());
+        // Templates without `{id}` can give several files the same name
+        let used = &std::sync::Mutex::new(std::collections::HashSet::new());
+        let mut results = pin!(
+            futures_util::stream::iter(ids)
+                .map(|id| {
+                    let dir = &dir;
+                    async move {
+                        let result = async {
+                            let info = client.get(&id).await?;
+                            let rendered = name.render(&info)?.to_string_lossy().into_owned();
+                            let path = {
+                                let mut used = used.lock().unwrap();
+                                let unique =
+                                    crate::template::unique_name(&rendered, &info.id, |name| {
+                                        used.contains(name)
+                                    });
+                                used.insert(unique.clone());
+                                dir.join(unique)
+                            };
+                            let mut size = client.download(&info, &path, force).await?;
+                            if embed {
+                                size = embed_into(&path, &info)?;
+                            }
+                            Ok::<_, crate::Error>((path, size))
+                        }
+                        .await;
+                        (id, result)
+                    }
+                })
+                .buffer_unordered(self.jobs.max(1))
+        );
+        while let Some((id, result)) = results.next().await {
+            match result {
+                Ok((path, size)) => println!("{id} {} {size} bytes", path.display()),
+                Err(error) => {
+                    failed += 1;
+                    println!("{id} Error: {}", error_chain(&error));
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("Failed to download {failed} of {total} files");
+        }
+        Ok(())
+    }
+
+    async fn run_one(&self, client: &Client, id: &ImageId) -> Result<()> {
         let info = client
-            .get(&self.id)
+            .get(id)
             .await
             .wrap_err("Failed to determine file information")?;
-        let mut byte_stream = reqwest::get(info.download.as_str())
-            .await
-            .wrap_err("Failed to connecto to file download URL")?
-            .bytes_stream();
-
-        let path = self.to.unwrap_or_else(|| info.name().into());
-        let path_str = path.display().to_string();
-        println!("File: {path_str}");
-        let mut file = if self.force {
-            fs::File::create(path)
-                .await
-                .wrap_err_with(|| format!("Failed to create file {path_str}"))?
-        } else {
-            fs::File::create_new(path)
+        if self.to_stdout() {
+            // Only when asked for, as the configured default is for files
+            if self.embed {
+                bail!("Cannot embed metadata when writing to stdout");
+            }
+            // Keep stdout for the file itself
+            eprintln!("File: {}", info.name());
+            let size = client
+                .download_to(&info, &mut std::io::stdout())
                 .await
-                .wrap_err_with(|| format!("Failed to create new file {path_str}"))?
+                .wrap_err("Failed to download to stdout")?;
+            eprintln!("Size: {size} bytes");
+            return Ok(());
+        }
+        let path = match self.to.clone() {
+            Some(path) => path,
+            None => self.name().render(&info)?,
         };
-        while let Some(bytes) = byte_stream.next().await {
-            tokio::io::copy(
-                &mut bytes
-                    .wrap_err("Failed to read bytes from file download URL")?
-                    .as_ref(),
-                &mut file,
-            )
+        let path_str = path.display().to_string();
+        println!("File: {path_str}");
+        let mut size = client
+            .download(&info, &path, self.force)
             .await
-            .wrap_err("Failed to copy bytes to destination")?;
+            .wrap_err_with(|| format!("Failed to download to {path_str}"))?;
+        if self.embed() {
+            size = embed_into(&path, &info)
+                .wrap_err_with(|| format!("Failed to embed metadata into {path_str}"))?;
         }
-        file.sync_all()
-            .await
-            .wrap_err("Failed to flush file to disk")?;
-        let size = file
-            .metadata()
-            .await
-            .wrap_err("Failed to determine size of downloaded file")?
-            .len();
         println!("Size: {size} bytes");
         Ok(())
     }
+
+    fn name(&self) -> crate::NameTemplate {
+        self.name
+            .clone()
+            .unwrap_or_else(|| crate::Config::load().download.name)
+    }
+
+    fn embed(&self) -> bool {
+        self.embed || crate::Config::load().download.embed
+    }
+
+    fn to_stdout(&self) -> bool {
+        self.to.as_deref() == Some(Path::new("-"))
+    }
+}
+
+// Returns the new size of the file
+fn embed_into(path: &Path, file: &crate::File) -> crate::Result<u64> {
+    crate::embed_metadata(path, file)?;
+    crate::file_size(path)
+}
+
+/// Mirror the library into a directory, fetching only new files
+#[derive(Args, Debug)]
+pub struct Backup {
+    /// File name template for new files, eg. "{create_time:%Y-%m}/{id}.{type}"
+    #[clap(long)]
+    pub name: Option<crate::NameTemplate>,
+    /// Write title, description, source URL and OCR text into new files
+    #[clap(action, long)]
+    pub embed: bool,
+    pub dir: PathBuf,
+}
+
+impl Backup {
+    pub async fn run(self, client: &Client) -> Result<()> {
+        let dir_str = self.dir.display().to_string();
+        let config = crate::Config::load().download;
+        let name = self.name.clone().unwrap_or(config.name);
+        let mut backup = crate::Backup::open(&self.dir, name, self.embed || config.embed)
+            .wrap_err_with(|| format!("Failed to open backup in {dir_str}"))?;
+
+        let mut seen = std::collections::HashSet::new();
+        let (mut fetched, mut present, mut failed) = (0, 0, 0);
+        let mut files = pin!(client.list().await);
+        while let Some(file) = files.next().await {
+            let file = match file {
+                Ok(file) => file,
+                Err(error) => {
+                    // Keep what was fetched, but the listing is incomplete
+                    backup.store()?;
+                    return Err(error).wrap_err("Failed to list files with API");
+                }
+            };
+            seen.insert(file.id.clone());
+            if backup.has(&file) {
+                present += 1;
+                continue;
+            }
+            match backup.add(client, &file).await {
+                Ok(size) => {
+                    fetched += 1;
+                    println!("{} {size} bytes", file.name());
+                    // So an interrupted run does not fetch it again
+                    backup.store().wrap_err_with(|| {
+                        format!("Failed to store backup manifest in {dir_str}")
+                    })?;
+                }
+                Err(error) => {
+                    failed += 1;
+                    eprintln!("{} Error: {}", file.name(), error_chain(&error));
+                }
+            }
+        }
+        for id in backup.mark_deleted(&seen) {
+            println!("{id} Deleted remotely");
+        }
+        backup
+            .store()
+            .wrap_err_with(|| format!("Failed to store backup manifest in {dir_str}"))?;
+
+        println!("Fetched {fetched}, already present {present}, failed {failed}");
+        if failed > 0 {
+            bail!("Failed to back up {failed} files");
+        }
+        Ok(())
+    }
+}
+
+/// Package files into an archive with an HTML gallery, for sharing
+#[derive(Args, Debug)]
+pub struct Export {
+    /// Archive format, zip or tar, by default from the extension of OUT
+    #[clap(long)]
+    pub format: Option<crate::ArchiveFormat>,
+    /// Only files created at or after this date or time, eg. 2024-01-31
+    #[clap(long, value_parser = parse_time)]
+    pub since: Option<jiff::Timestamp>,
+    /// Only files created before this date or time
+    #[clap(long, value_parser = parse_time)]
+    pub before: Option<jiff::Timestamp>,
+    /// Only files captured in this app
+    #[clap(long)]
+    pub app: Option<String>,
+    /// Export files read from stdin instead, one ID or `gyazo list` line each
+    #[clap(action, long)]
+    pub stdin: bool,
+    /// File name template, eg. "{create_time:%Y-%m-%d} {title}.{type}"
+    #[clap(long)]
+    pub name: Option<crate::NameTemplate>,
+    pub out: PathBuf,
+}
+
+impl Export {
+    pub async fn run(self, client: &Client) -> Result<()> {
+        let out_str = self.out.display().to_string();
+        let format = self
+            .format
+            .or_else(|| crate::ArchiveFormat::guess(&self.out))
+            .unwrap_or_default();
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| crate::Config::load().download.name);
+        // Before creating the archive, so failing leaves nothing behind
+        let lines = if self.stdin {
+            Some(stdin_lines()?)
+        } else {
+            None
+        };
+        let mut export = crate::Export::create(&self.out, format, name)
+            .wrap_err_with(|| format!("Failed to create archive {out_str}"))?;
+
+        // With the line each file was read from, if any
+        let mut files = if let Some(lines) = lines {
+            futures_util::stream::iter(lines)
+                .then(|line| async move {
+                    // `gyazo list` prints whole files as JSON
+                    let file = match serde_json::from_str::<crate::File>(&line) {
+                        Ok(file) => Ok(file),
+                        Err(_) => match line.parse() {
+                            Ok(id) => client.get(&id).await,
+                            Err(error) => Err(error),
+                        },
+                    };
+                    (Some(line), file)
+                })
+                .boxed()
+        } else {
+            client.list().await.map(|file| (None, file)).boxed()
+        };
+
+        let (mut exported, mut failed) = (0, 0);
+        while let Some((line, file)) = files.next().await {
+            let file = match (file, line) {
+                (Ok(file), _) => file,
+                // Bad lines are reported and skipped, failing only at the end
+                (Err(error), Some(line)) => {
+                    failed += 1;
+                    eprintln!("{line} Error: {}", error_chain(&error));
+                    continue;
+                }
+                (Err(error), None) => {
+                    export.discard();
+                    return Err(error).wrap_err("Failed to determine file information with API");
+                }
+            };
+            if !self.includes(&file) {
+                continue;
+            }
+            match export.add(client, &file).await {
+                Ok(size) => {
+                    exported += 1;
+                    println!("{} {size} bytes", file.name());
+                }
+                // Nothing more can be written after a truncated entry
+                Err(error) if export.is_broken() => {
+                    export.discard();
+                    return Err(error)
+                        .wrap_err_with(|| format!("Failed to write archive {out_str}"));
+                }
+                Err(error) => {
+                    failed += 1;
+                    println!("{} Error: {}", file.name(), error_chain(&error));
+                }
+            }
+        }
+        let path = export
+            .finish()
+            .wrap_err_with(|| format!("Failed to finish archive {out_str}"))?;
+
+        println!("Exported {exported} files to {}", path.display());
+        if failed > 0 {
+            bail!("Failed to export {failed} files");
+        }
+        Ok(())
+    }
+
+    fn includes(&self, file: &crate::File) -> bool {
+        let time = file.create_time.parse::<jiff::Timestamp>().ok();
+        let app = file.meta.as_ref().and_then(|meta| meta.app.as_deref());
+        self.since
+            .is_none_or(|since| time.is_some_and(|time| time >= since))
+            && self
+                .before
+                .is_none_or(|before| time.is_some_and(|time| time < before))
+            && self
+                .app
+                .as_deref()
+                .is_none_or(|wanted| app.is_some_and(|app| app.eq_ignore_ascii_case(wanted)))
+    }
+}
+
+// A timestamp, or a date meaning its start in the local time zone
+fn parse_time(s: &str) -> Result<jiff::Timestamp> {
+    if let Ok(timestamp) = s.parse() {
+        return Ok(timestamp);
+    }
+    let date: jiff::civil::Date = s
+        .parse()
+        .wrap_err_with(|| format!("Invalid date or time `{s}`, expected eg. 2024-01-31"))?;
+    Ok(date
+        .to_zoned(jiff::tz::TimeZone::system())
+        .wrap_err_with(|| format!("Invalid date `{s}`"))?
+        .timestamp())
+}
+
+// Only what images can be converted to
+fn parse_image_target(s: &str) -> Result<crate::FileKind> {
+    let kind: crate::FileKind = s.parse()?;
+    if !kind.is_accepted() || kind.is_video() {
+        bail!("Cannot convert images to {kind}, expected png, jpeg or gif");
+    }
+    Ok(kind)
 }
 
 #[derive(Args, Debug)]
 pub struct Upload {
     #[clap(flatten)]
     upload: UploadArgs,
-    pub file: PathBuf,
+    #[clap(flatten)]
+    copy: CopyArgs,
+    /// Upload videos other than MP4 as they are
+    #[clap(action, long)]
+    pub no_transcode: bool,
+    /// Only upload files within directories matching this pattern
+    #[clap(long = "glob", short, value_name = "PATTERN")]
+    pub globs: Vec<glob::Pattern>,
+    /// Also read paths to upload from stdin, one per line
+    #[clap(action, long)]
+    pub stdin: bool,
+    /// Upload the image on the clipboard instead of files
+    #[clap(action, conflicts_with_all = ["stdin", "files"], long)]
+    pub clipboard: bool,
+    /// How many files to upload at once
+    #[clap(long, short, default_value_t = 4)]
+    pub jobs: usize,
+    /// Files, or directories to upload recursively
+    #[clap(required_unless_present_any = ["stdin", "clipboard"])]
+    pub files: Vec<PathBuf>,
 }
 
 impl Upload {
     pub async fn run(self, client: &Client) -> Result<()> {
-        let file = &self.file;
-        let is_mp4 = Some("mp4".as_ref()) == file.extension();
-        let upload = self.upload.update(crate::Upload::default());
-        let file_str = file.display().to_string();
-        if is_mp4 {
-            let url = client
-                .upload_video(file)
-                .await
-                .wrap_err_with(|| format!("Failed to upload video file {file_str}"))?;
-            println!("URL: {url}");
+        let mut upload = self.upload.update(crate::Upload::default());
+        upload.transcode = !self.no_transcode;
+
+        if self.clipboard {
+            return self.run_clipboard(client, upload).await;
+        }
+
+        let mut inputs = self.files.clone();
+        if self.stdin {
+            inputs.extend(stdin_lines()?.into_iter().map(PathBuf::from));
+        }
+
+        if let [file] = inputs.as_slice()
+            && !self.stdin
+            && !file.is_dir()
+        {
+            return self.run_one(client, file, upload).await;
+        }
+
+        let mut paths = Vec::new();
+        for input in inputs {
+            self.collect(&input, &input, &mut paths)?;
+        }
+        let total = paths.len();
+        let bar = if std::io::stderr().is_terminal() {
+            ProgressBar::new(total as u64)
+                .with_style(ProgressStyle::with_template("{bar:40} {pos}/{len} files").unwrap())
         } else {
-            let (url, device) = client
-                .upload_image_cgi(file, &upload)
-                .await
-                .wrap_err_with(|| format!("Failed to upload image file {file_str}"))?;
+            ProgressBar::hidden()
+        };
+
+        let mut failed = 0;
+        // Copied together once done, as text since there may be many
+        let copy = self.copy.target();
+        let mut copied = Vec::new();
+        let mut results = pin!(client.upload_many(paths, &upload, self.jobs));
+        while let Some((path, result)) = results.next().await {
+            let line = match result {
+                Ok(uploaded) => {
+                    if let Some(target) = copy {
+                        copied.push(target.text(&uploaded));
+                    }
+                    serde_json::json!({ "path": path, "url": uploaded.url(), "uploaded": uploaded })
+                }
+                Err(error) => {
+                    failed += 1;
+                    serde_json::json!({ "path": path, "error": error_chain(&error) })
+                }
+            };
+            bar.suspend(|| println!("{}", compact(&line)));
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        if !copied.is_empty() {
+            crate::copy_to_clipboard(crate::Session::detect(), copied.join("\n").as_bytes(), None)
+                .wrap_err("Failed to copy to the clipboard")?;
+            // Not on stdout, which is JSON lines
+            eprintln!("Copied {} uploads to the clipboard", copied.len());
+        }
+        if failed > 0 {
+            bail!("Failed to upload {failed} of {total} files");
+        }
+        Ok(())
+    }
+
+    async fn run_one(&self, client: &Client, file: &Path, mut upload: crate::Upload) -> Result<()> {
+        let bar = progress_bar(&mut upload);
+        let file_str = file.display().to_string();
+        let uploaded = client
+            .upload(file, &upload)
+            .await
+            .wrap_err_with(|| format!("Failed to upload file {file_str}"));
+        bar.finish_and_clear();
+        let uploaded = uploaded?;
+        if uploaded.existing {
+            println!("Already uploaded");
+        }
+        if let Some(device) = uploaded.device.as_deref() {
             println!("Device: {device}");
-            println!("URL: {url}");
+        }
+        println!("URL: {}", uploaded.url());
+        self.copy.copy(&uploaded, file)?;
+        notify_uploaded(&uploaded, file).await;
+        Ok(())
+    }
+
+    async fn run_clipboard(&self, client: &Client, mut upload: crate::Upload) -> Result<()> {
+        let pasted = crate::clipboard_image(crate::Session::detect())
+            .wrap_err("Failed to read an image from the clipboard")?;
+        println!("Uploading {} bytes", pasted.size);
+        let bar = progress_bar(&mut upload);
+        let uploaded = client.upload(pasted.path(), &upload).await;
+        bar.finish_and_clear();
+        let uploaded = uploaded
+            .map_err(|error| queue_failed(pasted.path(), &upload, error, "clipboard image"))?;
+        if uploaded.existing {
+            println!("Already uploaded");
+        }
+        println!("URL: {}", uploaded.url());
+        self.copy.copy(&uploaded, pasted.path())?;
+        notify_uploaded(&uploaded, pasted.path()).await;
+        Ok(())
+    }
+
+    // Files are taken as given, directories are walked in name order
+    // keeping files whose path below `root` matches any of the globs.
+    // Symlinked directories below `root` are skipped, as they may loop.
+    fn collect(&self, root: &Path, path: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+        if path != root && path.is_symlink() && path.is_dir() {
+            return Ok(());
+        }
+        if !path.is_dir() {
+            if path == root || self.matches(path.strip_prefix(root).unwrap_or(path)) {
+                paths.push(path.to_path_buf());
+            }
+            return Ok(());
+        }
+        let path_str = path.display().to_string();
+        let mut entries = std::fs::read_dir(path)
+            .wrap_err_with(|| format!("Failed to read directory {path_str}"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err_with(|| format!("Failed to read directory {path_str}"))?;
+        entries.sort();
+        for entry in entries {
+            self.collect(root, &entry, paths)?;
         }
         Ok(())
     }
+
+    fn matches(&self, path: &Path) -> bool {
+        self.globs.is_empty() || self.globs.iter().any(|glob| glob.matches_path(path))
+    }
+}
+
+/// Captures that failed to upload, kept to retry later
+#[derive(Args, Debug)]
+pub struct Queue {
+    #[clap(subcommand)]
+    pub command: QueueCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QueueCommand {
+    #[clap(alias = "ls")]
+    List,
+    /// Retry uploading, removing what succeeds
+    Flush {
+        /// Only these queued IDs, rather than all
+        ids: Vec<crate::ContentId>,
+    },
+    /// Remove without uploading
+    #[clap(alias = "rm")]
+    Drop {
+        #[clap(action, conflicts_with = "ids", long, required_unless_present = "ids")]
+        all: bool,
+        ids: Vec<crate::ContentId>,
+    },
+}
+
+impl Queue {
+    pub async fn run(self, client: &Client) -> Result<()> {
+        match self.command {
+            QueueCommand::List => {
+                for queued in crate::Queue::list().wrap_err("Failed to list upload queue")? {
+                    println!("{}", compact(&queued));
+                }
+            }
+            QueueCommand::Flush { ids } => {
+                let (mut flushed, mut failed) = (0, 0);
+                for queued in Self::select(&ids)? {
+                    let id = &queued.id;
+                    match client.upload(&queued.file, &queued.upload).await {
+                        Ok(uploaded) => {
+                            queued.remove()?;
+                            flushed += 1;
+                            println!("{id} URL: {}", uploaded.url());
+                        }
+                        Err(error) => {
+                            failed += 1;
+                            println!("{id} Error: {}", error_chain(&error));
+                        }
+                    }
+                }
+                if failed > 0 {
+                    bail!(
+                        "Failed to upload {failed} of {} queued files",
+                        flushed + failed
+                    );
+                }
+            }
+            QueueCommand::Drop { all, ids } => {
+                let queue = if all {
+                    crate::Queue::list().wrap_err("Failed to list upload queue")?
+                } else {
+                    Self::select(&ids)?
+                };
+                for queued in queue {
+                    queued.remove()?;
+                    println!("Dropped {}", queued.id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // All queued uploads if no IDs are given
+    fn select(ids: &[crate::ContentId]) -> Result<Vec<crate::Queued>> {
+        let queue = crate::Queue::list().wrap_err("Failed to list upload queue")?;
+        if ids.is_empty() {
+            return Ok(queue);
+        }
+        ids.iter()
+            .map(|id| {
+                queue
+                    .iter()
+                    .find(|queued| queued.id == *id)
+                    .cloned()
+                    .ok_or_else(|| color_eyre::eyre::eyre!("No queued upload with ID {id}"))
+            })
+            .collect()
+    }
 }
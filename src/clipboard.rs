@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    io::Write as _,
+    path::Path,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use tempfile::NamedTempFile;
+
+use crate::{Captured, Error, FileKind, Result, Session, Uploaded, WrapErr as _, command};
+
+/// What to copy to the clipboard after uploading
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyTarget {
+    /// The permalink
+    #[default]
+    Link,
+    /// The URL of the file itself
+    Direct,
+    Markdown,
+    Html,
+    /// The image itself, or the link for videos
+    Image,
+}
+
+impl CopyTarget {
+    // As text, where `Image` falls back to the link
+    pub fn text(self, uploaded: &Uploaded) -> String {
+        let link = uploaded.url();
+        let direct = &uploaded.download;
+        let video = FileKind::from_str(&uploaded.file_type).is_ok_and(FileKind::is_video);
+        match self {
+            CopyTarget::Link | CopyTarget::Image => link.to_string(),
+            CopyTarget::Direct => direct.to_string(),
+            // As the Gyazo website shares them
+            CopyTarget::Markdown if video => format!("[Video from Gyazo]({link})"),
+            CopyTarget::Markdown => format!("[![Image from Gyazo]({direct})]({link})"),
+            CopyTarget::Html if video => {
+                format!(
+                    "<a href=\"{link}\"><video src=\"{direct}\" autoplay muted loop></video></a>"
+                )
+            }
+            CopyTarget::Html => {
+                format!("<a href=\"{link}\"><img src=\"{direct}\" alt=\"Image from Gyazo\"></a>")
+            }
+        }
+    }
+}
+
+impl fmt::Display for CopyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CopyTarget::Link => "link",
+            CopyTarget::Direct => "direct",
+            CopyTarget::Markdown => "markdown",
+            CopyTarget::Html => "html",
+            CopyTarget::Image => "image",
+        })
+    }
+}
+
+impl FromStr for CopyTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "link" => CopyTarget::Link,
+            "direct" => CopyTarget::Direct,
+            "markdown" | "md" => CopyTarget::Markdown,
+            "html" => CopyTarget::Html,
+            "image" => CopyTarget::Image,
+            _ => {
+                return Err(Error::Unsupported {
+                    message: format!(
+                        "Unknown copy target `{s}`, expected link, direct, markdown, html or image"
+                    ),
+                });
+            }
+        })
+    }
+}
+
+// Copies an upload of the local `path`, returning what was copied
+// as videos and unknown files are copied as links instead of images
+pub fn copy_upload(
+    session: Session,
+    target: CopyTarget,
+    uploaded: &Uploaded,
+    path: &Path,
+) -> Result<CopyTarget> {
+    if target == CopyTarget::Image
+        && let Ok(kind) = FileKind::detect(path)
+        && !kind.is_video()
+    {
+        let path_str = path.display().to_string();
+        let bytes = std::fs::read(path).wrap_err(format!("Could not read {path_str}"))?;
+        copy_to_clipboard(session, &bytes, Some(kind.mime()))?;
+        return Ok(target);
+    }
+    copy_to_clipboard(session, target.text(uploaded).as_bytes(), None)?;
+    Ok(match target {
+        CopyTarget::Image => CopyTarget::Link,
+        target => target,
+    })
+}
+
+// With `wl-copy` on Wayland or `xclip` on X11, as text unless a MIME type is given.
+// Both stay in the background to serve the clipboard until it changes.
+pub fn copy_to_clipboard(session: Session, bytes: &[u8], mime: Option<&str>) -> Result<()> {
+    let mut command = if session == Session::Wayland {
+        let mut wl_copy = Command::new("wl-copy");
+        if let Some(mime) = mime {
+            wl_copy.arg("--type").arg(mime);
+        }
+        wl_copy
+    } else {
+        let mut xclip = Command::new("xclip");
+        xclip.args(["-selection", "clipboard", "-i"]);
+        if let Some(mime) = mime {
+            xclip.arg("-t").arg(mime);
+        }
+        xclip
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    if !command::exists(&program) {
+        return Err(Error::Missing {
+            message: format!("Copying to the clipboard on {session} needs `{program}`"),
+        });
+    }
+    let message = format!("Could not copy to the clipboard with {program}");
+    let mut spawned = command::spawn(
+        command.stdin(Stdio::piped()).stdout(Stdio::null()),
+        message.clone(),
+    )?;
+    let written = spawned
+        .child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(bytes));
+    spawned.wait()?;
+    if let Some(written) = written {
+        written.wrap_err(message)?;
+    }
+    Ok(())
+}
+
+// Reads the clipboard with `wl-paste` on Wayland or `xclip` on X11,
+// taking PNG when offered as apps often offer several formats
+pub fn clipboard_image(session: Session) -> Result<Captured> {
+    let (program, list, read): (_, &[&str], &[&str]) = if session == Session::Wayland {
+        ("wl-paste", &["--list-types"], &["--no-newline", "--type"])
+    } else {
+        (
+            "xclip",
+            &["-selection", "clipboard", "-o", "-t", "TARGETS"],
+            &["-selection", "clipboard", "-o", "-t"],
+        )
+    };
+    if !command::exists(program) {
+        return Err(Error::Missing {
+            message: format!("Reading the clipboard on {session} needs `{program}`"),
+        });
+    }
+    let message = format!("Could not read the clipboard with {program}");
+
+    let no_image = || Error::Missing {
+        message: "The clipboard holds no image".to_string(),
+    };
+    let types = match command::run(Command::new(program).args(list), message.clone()) {
+        Ok(types) => types,
+        // As they say when nothing is copied at all
+        Err(Error::Command { stderr, .. })
+            if stderr.contains("Nothing is copied") || stderr.contains("not available") =>
+        {
+            return Err(no_image());
+        }
+        Err(error) => return Err(error),
+    };
+    let types = String::from_utf8_lossy(&types);
+    let types: Vec<&str> = types.lines().map(str::trim).collect();
+    let mime = types
+        .iter()
+        .find(|mime| **mime == "image/png")
+        .or_else(|| types.iter().find(|mime| mime.starts_with("image/")))
+        .ok_or_else(no_image)?;
+
+    let bytes = command::run(Command::new(program).args(read).arg(mime), message)?;
+    let kind = FileKind::sniff(&bytes)
+        .filter(|kind| !kind.is_video())
+        .ok_or_else(|| Error::Unsupported {
+            message: format!("The clipboard holds an image of unsupported type {mime}"),
+        })?;
+    let mut file = NamedTempFile::with_suffix(format!(".{}", kind.extension()))
+        .wrap_err("Could not create a temporary file for the clipboard image")?;
+    file.write_all(&bytes)
+        .wrap_err("Could not write the clipboard image to a temporary file")?;
+    Ok(Captured {
+        file,
+        size: bytes.len() as u64,
+    })
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs,
+    io::{Read as _, Seek as _},
+    process::{Child, Command},
+};
+
+use crate::{Error, Result, WrapErr as _};
+
+// Runs an external tool to completion, failing on a non-zero exit
+// with whatever it printed to stderr, and returns its stdout.
+pub(crate) fn run<D>(command: &mut Command, msg: D) -> Result<Vec<u8>>
+where
+    D: core::fmt::Display + Send + Sync + 'static,
+{
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .wrap_err(format!("{msg} (could not run `{program}`)"))?;
+    if !output.status.success() {
+        return Err(Error::Command {
+            message: msg.to_string(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+// A tool started with `spawn`, whose stderr is kept aside in a temporary file
+pub(crate) struct Spawned {
+    pub(crate) child: Child,
+    stderr: fs::File,
+    message: String,
+    program: String,
+}
+
+// Starts an external tool with its stderr going to a temporary file rather
+// than a pipe, which nothing would read while it runs, and which a tool
+// forking into the background would hold open
+pub(crate) fn spawn(command: &mut Command, message: String) -> Result<Spawned> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let stderr = tempfile::tempfile().wrap_err("Could not create a temporary file")?;
+    let stderr_writer = stderr
+        .try_clone()
+        .wrap_err("Could not create a temporary file")?;
+    let child = command
+        .stderr(stderr_writer)
+        .spawn()
+        .wrap_err(format!("{message} (could not run `{program}`)"))?;
+    Ok(Spawned {
+        child,
+        stderr,
+        message,
+        program,
+    })
+}
+
+impl Spawned {
+    // Fails on a non-zero exit with whatever it printed to stderr
+    pub(crate) fn wait(&mut self) -> Result<()> {
+        let status = self.child.wait().wrap_err(format!(
+            "{} (`{}` did not exit)",
+            self.message, self.program
+        ))?;
+        if !status.success() {
+            let mut output = String::new();
+            self.stderr.rewind().ok();
+            self.stderr.read_to_string(&mut output).ok();
+            return Err(Error::Command {
+                message: self.message.clone(),
+                status,
+                stderr: output.trim().to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// Whether an executable of the given name is on `$PATH`
+pub(crate) fn exists(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
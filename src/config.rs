@@ -1,6 +1,6 @@
 use std::{fs, path::PathBuf};
 
-use crate::Result;
+use crate::{CopyTarget, FileKind, NameTemplate, Result};
 
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Config {
@@ -15,18 +15,52 @@ pub struct Config {
     pub key: Option<String>,
     #[serde(default, skip_serializing_if = "Upload::is_default")]
     pub upload: Upload,
+    #[serde(default, skip_serializing_if = "Download::is_default")]
+    pub download: Download,
+    #[serde(default, skip_serializing_if = "Capture::is_default")]
+    pub capture: Capture,
+    #[serde(default, skip_serializing_if = "Notify::is_default")]
+    pub notify: Notify,
 }
 
-// fn yes() -> bool {
-//     true
-// }
+fn yes() -> bool {
+    true
+}
 
-#[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
+fn png() -> FileKind {
+    FileKind::Png
+}
+
+fn ten() -> u64 {
+    10
+}
+
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Upload {
     // #[serde(default = "yes")]
     // pub public_access: bool,
     #[serde(default)]
     pub public_metadata: bool,
+    /// Format for images Gyazo does not accept, one of png, jpeg or gif
+    #[serde(default = "png")]
+    pub convert_to: FileKind,
+    /// Skip files that were uploaded before
+    #[serde(default = "yes")]
+    pub dedup: bool,
+    /// Copy to the clipboard after uploading, one of link, direct, markdown, html or image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy: Option<CopyTarget>,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self {
+            public_metadata: false,
+            convert_to: png(),
+            dedup: yes(),
+            copy: None,
+        }
+    }
 }
 
 impl Upload {
@@ -35,6 +69,96 @@ impl Upload {
     }
 }
 
+#[derive(Clone, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Download {
+    /// File names for downloads and backups, eg. "{create_time:%Y-%m-%d} {title}.{type}"
+    #[serde(default)]
+    pub name: NameTemplate,
+    /// Write title, description, source URL and OCR text into downloaded files
+    #[serde(default)]
+    pub embed: bool,
+}
+
+impl Download {
+    fn is_default(&self) -> bool {
+        *self == Default::default()
+    }
+}
+
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Capture {
+    /// Screenshot tool, one of import, maim, scrot, grim, gnome-screenshot
+    /// or spectacle, detected from $PATH and the session when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Screen recorder, one of ffmpeg, wf-recorder or portal,
+    /// detected from $PATH and the session when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorder: Option<String>,
+    /// Send the focused window's app and title, and a browser's page URL, with captures
+    #[serde(default = "yes")]
+    pub window_metadata: bool,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            recorder: None,
+            window_metadata: yes(),
+        }
+    }
+}
+
+impl Capture {
+    fn is_default(&self) -> bool {
+        *self == Default::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyWhen {
+    /// When not run from a terminal, as from a hotkey
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Desktop notifications for capturing and uploading
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Notify {
+    /// One of auto, always or never
+    #[serde(default)]
+    pub when: NotifyWhen,
+    /// Show the link of uploads, opening it when clicked
+    #[serde(default = "yes")]
+    pub uploads: bool,
+    #[serde(default = "yes")]
+    pub errors: bool,
+    /// Seconds to show notifications for, and to wait for a click
+    #[serde(default = "ten")]
+    pub timeout: u64,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self {
+            when: NotifyWhen::default(),
+            uploads: yes(),
+            errors: yes(),
+            timeout: ten(),
+        }
+    }
+}
+
+impl Notify {
+    fn is_default(&self) -> bool {
+        *self == Default::default()
+    }
+}
+
 impl Config {
     pub fn load() -> Self {
         fs::read_to_string(Self::path())
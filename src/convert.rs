@@ -0,0 +1,228 @@
+use std::{
+    fs,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use image::{
+    AnimationDecoder as _, DynamicImage, ImageFormat, ImageReader,
+    codecs::{
+        gif::{GifEncoder, Repeat},
+        webp::WebPDecoder,
+    },
+};
+use tempfile::NamedTempFile;
+
+use crate::{Error, FileKind, Result, Upload, WrapErr as _, command};
+
+/// A file ready for upload, converted or transcoded if needed
+pub struct Prepared {
+    original: PathBuf,
+    converted: Option<NamedTempFile>,
+    pub kind: FileKind,
+}
+
+impl Prepared {
+    pub fn path(&self) -> &Path {
+        self.converted
+            .as_ref()
+            .map_or(&self.original, |file| file.path())
+    }
+
+    pub fn is_converted(&self) -> bool {
+        self.converted.is_some()
+    }
+}
+
+pub fn prepare(path: &Path, upload: &Upload) -> Result<Prepared> {
+    let kind = FileKind::detect(path)?;
+    let converted = if !kind.is_video() {
+        convert_image(path, upload.convert_to)?
+    } else if upload.transcode {
+        transcode_video(path)?
+    } else {
+        None
+    };
+    let kind = match converted.as_ref() {
+        Some(file) => FileKind::detect(file.path())?,
+        None => kind,
+    };
+    Ok(Prepared {
+        original: path.to_path_buf(),
+        converted,
+        kind,
+    })
+}
+
+// Converts images that Gyazo does not accept into `target`,
+// or into a GIF when animated, returning `None` when the
+// file can be uploaded as is.
+pub fn convert_image(path: &Path, target: FileKind) -> Result<Option<NamedTempFile>> {
+    if !target.is_accepted() || target.is_video() {
+        return Err(Error::Unsupported {
+            message: format!("Cannot convert images to {target}, expected png, jpeg or gif"),
+        });
+    }
+    let kind = FileKind::detect(path)?;
+    if kind.is_video() {
+        return Err(Error::Unsupported {
+            message: format!("Cannot convert {kind} video {} to an image", path.display()),
+        });
+    }
+    if kind.is_accepted() {
+        return Ok(None);
+    }
+    match kind {
+        // The `image` crate can only decode these with native libraries
+        FileKind::Avif | FileKind::Heic => magick(path, target).map(Some),
+        _ => decode(path, kind, target).map(Some),
+    }
+}
+
+fn decode(path: &Path, kind: FileKind, target: FileKind) -> Result<NamedTempFile> {
+    let path_str = path.display().to_string();
+    let open = || {
+        fs::File::open(path)
+            .map(BufReader::new)
+            .wrap_err(format!("Could not open {path_str} for conversion"))
+    };
+
+    if kind == FileKind::WebP {
+        let decoder =
+            WebPDecoder::new(open()?).wrap_err(format!("Could not decode {path_str} as WebP"))?;
+        if decoder.has_animation() {
+            let frames = decoder
+                .into_frames()
+                .collect_frames()
+                .wrap_err(format!("Could not decode frames of animated {path_str}"))?;
+            let file = temp_file(FileKind::Gif)?;
+            // The GIF trailer is written when the encoder is dropped
+            {
+                let mut encoder = GifEncoder::new(BufWriter::new(file.as_file()));
+                encoder
+                    .set_repeat(Repeat::Infinite)
+                    .wrap_err("Could not make converted GIF loop")?;
+                encoder
+                    .encode_frames(frames)
+                    .wrap_err(format!("Could not encode animated {path_str} as GIF"))?;
+            }
+            return Ok(file);
+        }
+    }
+
+    let format = match kind {
+        FileKind::Bmp => ImageFormat::Bmp,
+        FileKind::Tiff => ImageFormat::Tiff,
+        _ => ImageFormat::WebP,
+    };
+    let mut image = ImageReader::with_format(open()?, format)
+        .decode()
+        .wrap_err(format!("Could not decode {path_str} as {kind}"))?;
+    let format = match target {
+        FileKind::Jpeg => {
+            // JPEG has no alpha channel
+            image = DynamicImage::ImageRgb8(image.to_rgb8());
+            ImageFormat::Jpeg
+        }
+        FileKind::Gif => ImageFormat::Gif,
+        _ => ImageFormat::Png,
+    };
+    let file = temp_file(target)?;
+    image
+        .write_to(&mut BufWriter::new(file.as_file()), format)
+        .wrap_err(format!("Could not encode {path_str} as {target}"))?;
+    Ok(file)
+}
+
+// Falls back to ImageMagick, keeping multi-frame inputs animated
+// like `gyazo.perl` did with `identify`.
+fn magick(path: &Path, target: FileKind) -> Result<NamedTempFile> {
+    let path_str = path.display().to_string();
+    let installed =
+        command::exists("magick") || command::exists("identify") && command::exists("convert");
+    if !installed {
+        return Err(Error::Missing {
+            message: format!(
+                "Converting {path_str} needs ImageMagick, either `magick` or `identify` and `convert`"
+            ),
+        });
+    }
+    let frames = command::run(
+        imagemagick("identify").arg(path),
+        format!("Could not identify {path_str} with ImageMagick to convert it"),
+    )?;
+    let animated = frames
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .count()
+        > 1;
+    let target = if animated { FileKind::Gif } else { target };
+
+    let file = temp_file(target)?;
+    command::run(
+        imagemagick("convert").arg(path).arg(file.path()),
+        format!("Could not convert {path_str} to {target} with ImageMagick"),
+    )?;
+    Ok(file)
+}
+
+// ImageMagick 7 bundles its tools into `magick`
+fn imagemagick(tool: &str) -> Command {
+    if command::exists("magick") {
+        let mut command = Command::new("magick");
+        if tool != "convert" {
+            command.arg(tool);
+        }
+        command
+    } else {
+        Command::new(tool)
+    }
+}
+
+// Re-encodes videos other than MP4 into H.264 MP4, with the same
+// yuv420p pixel format `cli::Video` records with, returning `None`
+// when the file can be uploaded as is.
+pub fn transcode_video(path: &Path) -> Result<Option<NamedTempFile>> {
+    let kind = FileKind::detect(path)?;
+    if !kind.is_video() {
+        return Err(Error::Unsupported {
+            message: format!(
+                "Cannot transcode {kind} image {} to a video",
+                path.display()
+            ),
+        });
+    }
+    if kind == FileKind::Mp4 {
+        return Ok(None);
+    }
+    let file = temp_file(FileKind::Mp4)?;
+    command::run(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-y"])
+            .arg("-i")
+            .arg(path)
+            .args([
+                "-c:v",
+                "libx264",
+                // without this, videos won't play on Windows
+                "-pix_fmt",
+                "yuv420p",
+                // which in turn needs even dimensions
+                "-vf",
+                "scale=trunc(iw/2)*2:trunc(ih/2)*2",
+                "-c:a",
+                "aac",
+                "-movflags",
+                "+faststart",
+            ])
+            .arg(file.path()),
+        format!("Could not transcode {} to MP4 with FFmpeg", path.display()),
+    )?;
+    Ok(Some(file))
+}
+
+fn temp_file(kind: FileKind) -> Result<NamedTempFile> {
+    NamedTempFile::with_suffix(format!(".{}", kind.extension()))
+        .wrap_err("Could not create a temporary file for conversion")
+}
@@ -0,0 +1,314 @@
+use std::{fs, io::Write as _, path::Path, process::Command};
+
+use tempfile::NamedTempFile;
+
+use crate::{Error, File, FileKind, Result, WrapErr as _, command};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// Segment lengths are 16 bits and include themselves
+const JPEG_SEGMENT_MAX: usize = 65533;
+
+// What we know about a file, as text fields
+struct Fields<'a> {
+    title: Option<&'a str>,
+    desc: Option<&'a str>,
+    app: Option<&'a str>,
+    url: Option<&'a str>,
+    ocr: Option<&'a str>,
+    permalink: &'a str,
+    create_time: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn new(file: &'a File) -> Self {
+        let meta = file.meta.as_ref();
+        let some = |value: Option<&'a str>| value.filter(|value| !value.trim().is_empty());
+        Self {
+            title: some(meta.and_then(|meta| meta.title.as_deref())),
+            desc: some(meta.map(|meta| meta.desc.as_str())),
+            app: some(meta.and_then(|meta| meta.app.as_deref())),
+            url: some(meta.and_then(|meta| meta.url.as_deref())),
+            ocr: some(file.ocr.as_ref().map(|ocr| ocr.description.as_str())),
+            permalink: file.permalink.as_str(),
+            create_time: &file.create_time,
+        }
+    }
+
+    // Description and recognised text together, for tools which only
+    // search a single description field
+    fn description(&self, with_ocr: bool) -> Option<String> {
+        let ocr = self.ocr.filter(|_| with_ocr);
+        match (self.desc, ocr) {
+            (Some(desc), Some(ocr)) => Some(format!("{desc}\n\n{ocr}")),
+            (desc, ocr) => desc.or(ocr).map(str::to_string),
+        }
+    }
+
+    fn xmp(&self, with_ocr: bool) -> String {
+        let mut xmp = String::from(concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "<rdf:Description rdf:about=\"\"",
+            " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+            " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n",
+        ));
+        let alt = |name: &str, value: &str| {
+            format!(
+                "<dc:{name}><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:{name}>\n",
+                escape(value)
+            )
+        };
+        if let Some(title) = self.title {
+            xmp.push_str(&alt("title", title));
+        }
+        if let Some(description) = self.description(with_ocr) {
+            xmp.push_str(&alt("description", &description));
+        }
+        if let Some(url) = self.url {
+            xmp.push_str(&format!("<dc:source>{}</dc:source>\n", escape(url)));
+        }
+        xmp.push_str(&format!(
+            "<dc:identifier>{}</dc:identifier>\n",
+            escape(self.permalink)
+        ));
+        if let Some(app) = self.app {
+            xmp.push_str(&format!(
+                "<xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+                escape(app)
+            ));
+        }
+        xmp.push_str(&format!(
+            "<xmp:CreateDate>{}</xmp:CreateDate>\n",
+            escape(self.create_time)
+        ));
+        xmp.push_str("</rdf:Description>\n</rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>");
+        xmp
+    }
+}
+
+// Writes the Gyazo metadata and OCR text into the downloaded file
+// itself, so desktop photo tools can search them: as XMP and text
+// chunks in PNG, XMP in JPEG, a comment in GIF and metadata atoms
+// in MP4, the latter with FFmpeg.
+pub fn embed_metadata(path: &Path, file: &File) -> Result<()> {
+    let fields = Fields::new(file);
+    let path_str = path.display().to_string();
+    match FileKind::detect(path)? {
+        FileKind::Png => rewrite(path, |bytes| png(bytes, &fields)),
+        FileKind::Jpeg => rewrite(path, |bytes| jpeg(bytes, &fields)),
+        FileKind::Gif => rewrite(path, |bytes| gif(bytes, &fields)),
+        FileKind::Mp4 | FileKind::Mov => mp4(path, &fields),
+        kind => Err(Error::Unsupported {
+            message: format!("Cannot embed metadata into {kind} file {path_str}"),
+        }),
+    }
+    .map_err(|error| match error {
+        Error::String(reason) => Error::String(format!(
+            "Could not embed metadata into {path_str}: {reason}"
+        )),
+        error => error,
+    })
+}
+
+// Replaces the file through a sibling, so it is never left half written
+fn rewrite(path: &Path, edit: impl FnOnce(&[u8]) -> Result<Vec<u8>>) -> Result<()> {
+    let path_str = path.display().to_string();
+    let bytes = fs::read(path).wrap_err(format!("Could not read {path_str}"))?;
+    let bytes = edit(&bytes)?;
+    let mut temp = sibling(path, "")?;
+    temp.write_all(&bytes)
+        .wrap_err(format!("Could not write metadata into {path_str}"))?;
+    replace(temp, path)
+}
+
+// A temporary file next to `path`, named apart from the `.part`
+// files of interrupted downloads, and removed unless it replaces it
+fn sibling(path: &Path, suffix: &str) -> Result<NamedTempFile> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    tempfile::Builder::new()
+        .prefix(".gyazo-embed-")
+        .suffix(suffix)
+        .tempfile_in(dir)
+        .wrap_err(format!(
+            "Could not create a temporary file next to {}",
+            path.display()
+        ))
+}
+
+// Keeping the permissions of the file, rather than the private ones of temporary files
+fn replace(temp: NamedTempFile, path: &Path) -> Result<()> {
+    let path_str = path.display().to_string();
+    fs::metadata(path)
+        .and_then(|meta| fs::set_permissions(temp.path(), meta.permissions()))
+        .wrap_err(format!("Could not copy permissions of {path_str}"))?;
+    temp.persist(path)
+        .map(drop)
+        .map_err(|error| error.error)
+        .wrap_err(format!("Could not replace {path_str}"))
+}
+
+fn malformed(kind: &str) -> Error {
+    Error::String(format!("Malformed {kind} file"))
+}
+
+fn png(bytes: &[u8], fields: &Fields) -> Result<Vec<u8>> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err(malformed("PNG"));
+    }
+    // Find IEND, as text chunks may not come after it
+    let mut offset = PNG_SIGNATURE.len();
+    let iend = loop {
+        let header = bytes
+            .get(offset..offset + 8)
+            .ok_or_else(|| malformed("PNG"))?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        if &header[4..] == b"IEND" {
+            break offset;
+        }
+        offset += 12 + len;
+    };
+
+    let mut chunks = Vec::new();
+    let mut text = |keyword: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            chunks.extend(png_itxt(keyword, value));
+        }
+    };
+    text("Title", fields.title);
+    text("Description", fields.description(true).as_deref());
+    text("Software", fields.app);
+    text("Source", fields.url);
+    text("Comment", Some(fields.permalink));
+    text(XMP_KEYWORD, Some(&fields.xmp(true)));
+
+    let mut out = Vec::with_capacity(bytes.len() + chunks.len());
+    out.extend_from_slice(&bytes[..iend]);
+    out.extend(chunks);
+    out.extend_from_slice(&bytes[iend..]);
+    Ok(out)
+}
+
+// Uncompressed international text, which is UTF-8
+fn png_itxt(keyword: &str, text: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(keyword.as_bytes());
+    // null separator, no compression, no language or translated keyword
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(text.as_bytes());
+
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(b"iTXt");
+    chunk.extend_from_slice(&data);
+    let crc = crc32fast::hash(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+fn jpeg(bytes: &[u8], fields: &Fields) -> Result<Vec<u8>> {
+    if !bytes.starts_with(b"\xff\xd8") {
+        return Err(malformed("JPEG"));
+    }
+    // After the JFIF and EXIF segments, where readers expect XMP
+    let mut offset = 2;
+    while let Some(&[0xff, 0xe0..=0xef, high, low, ..]) = bytes.get(offset..) {
+        offset += 2 + u16::from_be_bytes([high, low]) as usize;
+    }
+    if offset > bytes.len() {
+        return Err(malformed("JPEG"));
+    }
+
+    let mut xmp = fields.xmp(true).into_bytes();
+    if JPEG_XMP_HEADER.len() + xmp.len() > JPEG_SEGMENT_MAX {
+        // Long OCR text would need extended XMP, spread over segments
+        xmp = fields.xmp(false).into_bytes();
+    }
+    if JPEG_XMP_HEADER.len() + xmp.len() > JPEG_SEGMENT_MAX {
+        return Err(Error::String(
+            "Metadata too long for a JPEG segment".to_string(),
+        ));
+    }
+    let len = (2 + JPEG_XMP_HEADER.len() + xmp.len()) as u16;
+
+    let mut out = Vec::with_capacity(bytes.len() + len as usize + 2);
+    out.extend_from_slice(&bytes[..offset]);
+    out.extend_from_slice(&[0xff, 0xe1]);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(JPEG_XMP_HEADER);
+    out.extend_from_slice(&xmp);
+    out.extend_from_slice(&bytes[offset..]);
+    Ok(out)
+}
+
+// GIF has no standard place for XMP that all readers agree on,
+// but comment extensions are widely shown
+fn gif(bytes: &[u8], fields: &Fields) -> Result<Vec<u8>> {
+    let Some((&0x3b, body)) = bytes.split_last() else {
+        return Err(malformed("GIF"));
+    };
+    let mut comment = String::new();
+    for value in [fields.title, fields.url, fields.app, Some(fields.permalink)]
+        .into_iter()
+        .flatten()
+    {
+        comment.push_str(value);
+        comment.push('\n');
+    }
+    if let Some(description) = fields.description(true) {
+        comment.push_str(&description);
+    }
+
+    let mut out = Vec::with_capacity(bytes.len() + comment.len() + comment.len() / 255 + 4);
+    out.extend_from_slice(body);
+    out.extend_from_slice(&[0x21, 0xfe]);
+    for block in comment.as_bytes().chunks(255) {
+        out.push(block.len() as u8);
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&[0, 0x3b]);
+    Ok(out)
+}
+
+fn mp4(path: &Path, fields: &Fields) -> Result<()> {
+    let path_str = path.display().to_string();
+    // With the extension FFmpeg picks the container by
+    let temp = sibling(path, ".mp4")?;
+
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(path)
+        .args(["-map", "0", "-c", "copy"]);
+    let mut metadata = |key: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            ffmpeg.arg("-metadata").arg(format!("{key}={value}"));
+        }
+    };
+    metadata("title", fields.title);
+    metadata("description", fields.description(true).as_deref());
+    metadata("comment", Some(fields.url.unwrap_or(fields.permalink)));
+    metadata("encoder", fields.app);
+    metadata("creation_time", Some(fields.create_time));
+    ffmpeg.arg(temp.path());
+
+    command::run(
+        &mut ffmpeg,
+        format!("Could not embed metadata into {path_str} with FFmpeg"),
+    )?;
+    replace(temp, path)
+}
+
+// For text and attribute values alike, in XML as in HTML
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
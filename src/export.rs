@@ -0,0 +1,356 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{self, Read, Seek as _},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    Client, Error, File, FileKind, NameTemplate, Result, Url, WrapErr as _,
+    embed::escape,
+    template::{format_time, unique_name},
+};
+
+/// Archive formats for `Export`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    // From the extension of `path`, if it names one
+    pub fn guess(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+        })
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => Err(Error::Unsupported {
+                message: format!("Unknown archive format `{s}`, expected zip or tar"),
+            }),
+        }
+    }
+}
+
+enum Archive {
+    Zip(Box<ZipWriter<fs::File>>),
+    Tar(tar::Builder<fs::File>),
+}
+
+/// An archive of files with an `index.json` of their records and an
+/// `index.html` gallery, for viewing without a Gyazo account
+///
+/// Everything goes into a directory named after the archive, with
+/// files in `files/` and thumbnails in `thumbs/`. The archive is
+/// written aside and only appears at its path once finished.
+pub struct Export {
+    path: PathBuf,
+    part: PathBuf,
+    root: String,
+    archive: Archive,
+    name: NameTemplate,
+    names: HashSet<String>,
+    entries: Vec<Entry>,
+    broken: bool,
+}
+
+struct Entry {
+    file: File,
+    name: String,
+    thumb: Option<String>,
+}
+
+impl Export {
+    pub fn create(path: &Path, format: ArchiveFormat, name: NameTemplate) -> Result<Self> {
+        let path_str = path.display().to_string();
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .wrap_err(format!("Could not create directory for {path_str}"))?;
+        }
+        let out = fs::File::create(&part).wrap_err(format!("Could not create {path_str}"))?;
+        let archive = match format {
+            ArchiveFormat::Zip => Archive::Zip(Box::new(ZipWriter::new(out))),
+            ArchiveFormat::Tar => Archive::Tar(tar::Builder::new(out)),
+        };
+        let root = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .filter(|stem| !stem.is_empty() && !stem.starts_with('.'))
+            .unwrap_or_else(|| "gyazo".to_string());
+        Ok(Self {
+            path: path.to_path_buf(),
+            part,
+            root,
+            archive,
+            name,
+            names: HashSet::new(),
+            entries: Vec::new(),
+            broken: false,
+        })
+    }
+
+    // Downloads the file and its thumbnail into the archive,
+    // returning the size of the file
+    pub async fn add(&mut self, client: &Client, file: &File) -> Result<u64> {
+        let name = self.name.render(file)?.to_string_lossy().into_owned();
+        // Templates without `{id}` may repeat names
+        let name = unique_name(&name, &file.id, |name| self.names.contains(name));
+        self.names.insert(name.clone());
+
+        // Spooled through a temporary file, as tar needs the size up front
+        let mut temp = tempfile::tempfile().wrap_err("Could not create a temporary file")?;
+        let size = client.download_to(file, &mut temp).await?;
+        temp.rewind().wrap_err("Could not rewind temporary file")?;
+        self.append(&format!("files/{name}"), &mut temp, size, &file.create_time)?;
+
+        // Thumbnails are a nicety, the gallery falls back to the file itself
+        let mut thumb = None;
+        if let Some(url) = &file.thumb {
+            let mut bytes = Vec::new();
+            if client.fetch_to(url.as_str(), &mut bytes).await.is_ok() {
+                let extension = FileKind::sniff(&bytes).map_or("jpg", FileKind::extension);
+                let thumb_name = format!("{}.{extension}", file.id);
+                self.append(
+                    &format!("thumbs/{thumb_name}"),
+                    &mut bytes.as_slice(),
+                    bytes.len() as u64,
+                    &file.create_time,
+                )?;
+                thumb = Some(thumb_name);
+            }
+        }
+
+        self.entries.push(Entry {
+            file: file.clone(),
+            name,
+            thumb,
+        });
+        Ok(size)
+    }
+
+    // Whether writing to the archive failed, leaving a truncated entry
+    // in it, so that adding more files or finishing it is pointless
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    // Writes the index files and moves the archive into place,
+    // or removes the unfinished archive if that fails
+    pub fn finish(self) -> Result<PathBuf> {
+        let part = self.part.clone();
+        let finished = self.write_index();
+        if finished.is_err() {
+            fs::remove_file(&part).ok();
+        }
+        finished
+    }
+
+    fn write_index(mut self) -> Result<PathBuf> {
+        let path_str = self.path.display().to_string();
+        let files: Vec<&File> = self.entries.iter().map(|entry| &entry.file).collect();
+        let json = serde_json::to_string_pretty(&files).unwrap();
+        let html = gallery(&self.entries);
+        let now = jiff::Timestamp::now().to_string();
+        self.append("index.json", &mut json.as_bytes(), json.len() as u64, &now)?;
+        self.append("index.html", &mut html.as_bytes(), html.len() as u64, &now)?;
+
+        let out = match self.archive {
+            Archive::Zip(zip) => zip
+                .finish()
+                .wrap_err(format!("Could not finish {path_str}"))?,
+            Archive::Tar(tar) => tar
+                .into_inner()
+                .wrap_err(format!("Could not finish {path_str}"))?,
+        };
+        out.sync_all()
+            .wrap_err(format!("Could not flush {path_str} to disk"))?;
+        fs::rename(&self.part, &self.path)
+            .wrap_err(format!("Could not move archive into place at {path_str}"))?;
+        Ok(self.path)
+    }
+
+    // Removes the unfinished archive, when giving up on it
+    pub fn discard(self) {
+        fs::remove_file(&self.part).ok();
+    }
+
+    fn append(
+        &mut self,
+        name: &str,
+        reader: &mut impl Read,
+        size: u64,
+        modified: &str,
+    ) -> Result<()> {
+        if self.broken {
+            return Err(Error::String(format!(
+                "Could not add {name} to archive after an earlier write failed"
+            )));
+        }
+        let result = self.write_entry(name, reader, size, modified);
+        self.broken = result.is_err();
+        result
+    }
+
+    fn write_entry(
+        &mut self,
+        name: &str,
+        reader: &mut impl Read,
+        size: u64,
+        modified: &str,
+    ) -> Result<()> {
+        let path = format!("{}/{name}", self.root);
+        let modified: Option<jiff::Timestamp> = modified.parse().ok();
+        match &mut self.archive {
+            Archive::Zip(zip) => {
+                // Images and videos are compressed already
+                let method = if name.starts_with("index.") {
+                    CompressionMethod::Deflated
+                } else {
+                    CompressionMethod::Stored
+                };
+                let mut options = SimpleFileOptions::default()
+                    .compression_method(method)
+                    .unix_permissions(0o644)
+                    .large_file(size > u32::MAX as u64);
+                if let Some(time) = modified.and_then(zip_time) {
+                    options = options.last_modified_time(time);
+                }
+                zip.start_file(path.as_str(), options)
+                    .wrap_err(format!("Could not add {path} to archive"))?;
+                io::copy(reader, zip).wrap_err(format!("Could not write {path} to archive"))?;
+            }
+            Archive::Tar(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size);
+                header.set_mode(0o644);
+                if let Some(time) = modified {
+                    header.set_mtime(time.as_second().max(0) as u64);
+                }
+                header.set_cksum();
+                tar.append_data(&mut header, &path, reader)
+                    .wrap_err(format!("Could not write {path} to archive"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Zip stores local time without a zone, as file browsers show it
+fn zip_time(timestamp: jiff::Timestamp) -> Option<zip::DateTime> {
+    let time = timestamp.to_zoned(jiff::tz::TimeZone::system());
+    zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+fn gallery(entries: &[Entry]) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n",
+        "<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+        "<title>Gyazo</title>\n<style>\n",
+        "body { font-family: sans-serif; margin: 1em; background: #f4f4f4; color: #222; }\n",
+        "main { display: grid; grid-template-columns: repeat(auto-fill, minmax(16em, 1fr)); gap: 1em; }\n",
+        "article { background: #fff; border-radius: 6px; padding: .5em; overflow-wrap: anywhere; }\n",
+        "article img, article video { width: 100%; max-height: 12em; object-fit: contain; background: #eee; }\n",
+        "h2 { font-size: 1em; margin: .4em 0; }\n",
+        "p { margin: .3em 0; font-size: .9em; }\n",
+        ".meta { color: #666; }\n",
+        "pre { white-space: pre-wrap; font-size: .8em; }\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+    html.push_str(&format!("<p>{} files</p>\n<main>\n", entries.len()));
+    for Entry { file, name, thumb } in entries {
+        let href = format!("files/{}", escape(&url_path(name)));
+        let meta = file.meta.as_ref();
+        html.push_str("<article>\n");
+        let preview = match thumb {
+            Some(thumb) => format!(
+                "<img src=\"thumbs/{}\" loading=\"lazy\" alt=\"\">",
+                escape(thumb)
+            ),
+            None if file.file_type == "mp4" => {
+                format!("<video src=\"{href}\" preload=\"metadata\" muted></video>")
+            }
+            None => format!("<img src=\"{href}\" loading=\"lazy\" alt=\"\">"),
+        };
+        html.push_str(&format!("<a href=\"{href}\">{preview}</a>\n"));
+        if let Some(title) = meta.and_then(|meta| meta.title.as_deref()) {
+            html.push_str(&format!("<h2>{}</h2>\n", escape(title)));
+        }
+        let time = format_time(&file.create_time, "%Y-%m-%d %H:%M")
+            .unwrap_or_else(|_| file.create_time.clone());
+        let mut line = escape(&time);
+        if let Some(app) = meta.and_then(|meta| meta.app.as_deref()) {
+            line.push_str(&format!(" &middot; {}", escape(app)));
+        }
+        html.push_str(&format!("<p class=\"meta\">{line}</p>\n"));
+        if let Some(url) = meta.and_then(|meta| meta.url.as_deref()) {
+            // Sent by whatever captured it, so no `javascript:` links
+            let linkable =
+                Url::parse(url).is_ok_and(|parsed| matches!(parsed.scheme(), "http" | "https"));
+            let url = escape(url);
+            if linkable {
+                html.push_str(&format!("<p><a href=\"{url}\">{url}</a></p>\n"));
+            } else {
+                html.push_str(&format!("<p>{url}</p>\n"));
+            }
+        }
+        if let Some(desc) = meta
+            .map(|meta| meta.desc.as_str())
+            .filter(|desc| !desc.is_empty())
+        {
+            html.push_str(&format!("<p>{}</p>\n", escape(desc)));
+        }
+        if let Some(ocr) = file.ocr.as_ref().filter(|ocr| !ocr.description.is_empty()) {
+            html.push_str(&format!(
+                "<details><summary>Text</summary><pre>{}</pre></details>\n",
+                escape(&ocr.description)
+            ));
+        }
+        html.push_str("</article>\n");
+    }
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+// Names from templates may contain spaces, `#` and `?`
+fn url_path(name: &str) -> String {
+    name.split('/')
+        .map(|segment| {
+            url::form_urlencoded::byte_serialize(segment.as_bytes())
+                .collect::<String>()
+                .replace('+', "%20")
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,196 @@
+use std::{fmt, str::FromStr};
+
+use crate::{Error, Result, Url};
+
+/// A validated image ID, parsed from the bare ID or any URL containing it
+///
+/// Accepts eg. `<id>`, `https://gyazo.com/<id>?token=...`,
+/// `https://i.gyazo.com/<id>.png` and `i.gyazo.com/download/<id>.mp4`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ImageId(String);
+
+// Image and content IDs alike are MD5 hashes in hex
+fn is_hex_id(s: &str) -> bool {
+    s.len() == 32 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl ImageId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn parse_bare(s: &str) -> Option<Self> {
+        // Direct URLs and file names carry the type as an extension
+        let id = s.split_once('.').map_or(s, |(id, _)| id);
+        is_hex_id(id).then(|| Self(id.to_ascii_lowercase()))
+    }
+}
+
+impl FromStr for ImageId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let invalid = || Error::InvalidId {
+            message: format!("`{s}` is not a Gyazo image ID, permalink or direct URL"),
+        };
+        if let Some(id) = Self::parse_bare(s) {
+            return Ok(id);
+        }
+
+        let url = if s.contains("://") {
+            Url::parse(s)
+        } else {
+            Url::parse(&format!("https://{s}"))
+        }
+        .map_err(|_| invalid())?;
+        let gyazo = url
+            .host_str()
+            .is_some_and(|host| host == "gyazo.com" || host.ends_with(".gyazo.com"));
+        if !gyazo {
+            return Err(invalid());
+        }
+        url.path_segments()
+            .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+            .and_then(Self::parse_bare)
+            .ok_or_else(invalid)
+    }
+}
+
+impl TryFrom<String> for ImageId {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ImageId> for String {
+    fn from(id: ImageId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<str> for ImageId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ImageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The MD5 hash of a file, which its upload gets as image ID,
+/// but is not known to Gyazo until then
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ContentId(String);
+
+impl ContentId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn from_digest(digest: &[u8]) -> Self {
+        Self(hex::encode(digest))
+    }
+}
+
+impl FromStr for ContentId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if is_hex_id(s) {
+            Ok(Self(s.to_ascii_lowercase()))
+        } else {
+            Err(Error::InvalidId {
+                message: format!("`{s}` is not a queued upload ID"),
+            })
+        }
+    }
+}
+
+impl TryFrom<String> for ContentId {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ContentId> for String {
+    fn from(id: ContentId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for ContentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "0123456789abcdef0123456789abcdef";
+
+    fn parse(s: &str) -> Result<String> {
+        s.parse::<ImageId>().map(String::from)
+    }
+
+    #[test]
+    fn image_id_from_bare_ids_and_urls() {
+        for s in [
+            ID.to_string(),
+            ID.to_ascii_uppercase(),
+            format!("  {ID}\n"),
+            format!("{ID}.png"),
+            format!("https://gyazo.com/{ID}"),
+            format!("https://gyazo.com/{ID}?token=abc"),
+            format!("https://gyazo.com/{ID}/"),
+            format!("gyazo.com/{ID}"),
+            format!("https://i.gyazo.com/{ID}.png"),
+            format!("i.gyazo.com/download/{ID}.mp4"),
+        ] {
+            assert_eq!(parse(&s).unwrap(), ID, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn image_id_rejects_other_hosts_and_lengths() {
+        for s in [
+            "",
+            &ID[1..],
+            &format!("{ID}0"),
+            &ID.replace('a', "g"),
+            &format!("https://example.com/{ID}"),
+            &format!("https://notgyazo.com/{ID}"),
+            &format!("https://gyazo.com.example.com/{ID}"),
+            "https://gyazo.com/",
+        ] {
+            assert!(
+                matches!(parse(s), Err(Error::InvalidId { .. })),
+                "{s:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn content_id_is_bare_only() {
+        let id: ContentId = ID.to_ascii_uppercase().parse().unwrap();
+        assert_eq!(id.as_str(), ID);
+        assert!(format!("{ID}.png").parse::<ContentId>().is_err());
+        assert!(
+            format!("https://gyazo.com/{ID}")
+                .parse::<ContentId>()
+                .is_err()
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Timestamp, Url};
+use crate::{Error, Result, StatusCode, Timestamp, Url, WrapErr as _};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Image {
@@ -44,9 +44,12 @@ pub struct File {
     pub create_time: Timestamp,
     pub download: Url,
     pub permalink: Url,
-    // pub thumb: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumb: Option<Url>,
     #[serde(default, skip_serializing_if = "empty")]
     pub meta: Option<Metadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr: Option<Ocr>,
 }
 
 impl File {
@@ -56,57 +59,80 @@ impl File {
 }
 
 impl Image {
-    pub async fn into_file(mut self) -> File {
-        self.fix_mp4().await;
+    // Resolve "gif" files that are really MP4 first, see `ListOptions`
+    pub fn into_file(self) -> File {
         let download = Url::parse(&self.download_url()).unwrap();
         let Image {
             image_id: id,
             permalink_url: permalink,
-            thumb_url: _thumb,
+            thumb_url: thumb,
             file_type,
             created_at: create_time,
             metadata: meta,
+            ocr,
             ..
         } = self;
         File {
             id,
             permalink,
             download,
-            // thumb: _thumb,
+            thumb,
             file_type,
             create_time,
             meta,
+            ocr,
         }
     }
 }
 
-impl Image {
-    fn mp4_download_url(&self) -> String {
-        format!("https://i.gyazo.com/download/{}.mp4", self.image_id)
+pub(crate) fn mp4_download_url(id: &str) -> String {
+    format!("https://i.gyazo.com/download/{id}.mp4")
+}
+
+// HEAD does not reveal whether the MP4 download exists,
+// so GET a single byte of it
+pub(crate) async fn mp4_exists(http: &reqwest::Client, id: &str) -> Result<bool> {
+    let status = http
+        .get(mp4_download_url(id))
+        .header(http::header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .wrap_err(format!("Could not check whether {id} is an MP4"))?
+        .status();
+    if status.is_success() {
+        Ok(true)
+    } else if matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE) {
+        Ok(false)
+    } else {
+        Err(Error::String(format!(
+            "Could not check whether {id} is an MP4 ({status})"
+        )))
+    }
+}
+
+pub(crate) fn download_url(id: &str, file_type: &str) -> String {
+    if file_type == "mp4" {
+        mp4_download_url(id)
+    } else {
+        format!("https://i.gyazo.com/{id}.{file_type}")
     }
+}
 
-    // The public API does not expose whether a "gif"
-    // is actually an "mp4" or not. The HEAD does not
-    // reveal if the MP4 download URL exists, so we
-    // GET it without consuming the body.
+impl Image {
+    // One request per call, leaving the "gif" if the check fails
+    #[deprecated(note = "`Client::get` and `Client::list` resolve MP4s, with caching")]
     pub async fn fix_mp4(&mut self) {
         if self.file_type == "gif"
-            && reqwest::get(self.mp4_download_url())
+            && mp4_exists(&reqwest::Client::new(), &self.image_id)
                 .await
-                .unwrap()
-                .status()
-                .is_success()
+                .unwrap_or(false)
         {
             self.file_type = "mp4".to_string();
         }
     }
 
     pub fn download_url(&self) -> String {
-        if &self.file_type == "mp4" {
-            self.mp4_download_url()
-        } else {
-            format!("https://i.gyazo.com/{}.{}", self.image_id, self.file_type)
-        }
+        download_url(&self.image_id, &self.file_type)
     }
 }
 
@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    io::{self, Write as _},
+    marker::PhantomData,
+    path::PathBuf,
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{Result, WrapErr as _};
+
+/// A file of one JSON `T` per line, only ever appended to
+///
+/// A torn line from an interrupted write only loses that entry.
+pub(crate) struct JsonlStore<T> {
+    path: PathBuf,
+    // What it is called in errors, such as "MP4 cache"
+    what: &'static str,
+    entries: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned + Serialize> JsonlStore<T> {
+    pub(crate) fn new(path: PathBuf, what: &'static str) -> Self {
+        Self {
+            path,
+            what,
+            entries: PhantomData,
+        }
+    }
+
+    // Every entry that parses, oldest first, and none before the first write
+    pub(crate) fn load(&self) -> Result<Vec<T>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error).wrap_err(format!("Could not read {}", self.what)),
+        };
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    // In a single write, so concurrent appends do not interleave lines
+    pub(crate) fn append(&self, entries: &[T]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry).unwrap());
+            lines.push('\n');
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).ok();
+        }
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .wrap_err(format!("Could not write to {}", self.what))
+    }
+}
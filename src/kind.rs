@@ -0,0 +1,264 @@
+use std::{fmt, fs, io::Read as _, path::Path, str::FromStr};
+
+use crate::{Error, Result, WrapErr as _};
+
+// Enough for every signature below, including the
+// EBML doc type which follows a variable length header.
+const SNIFF_LEN: usize = 64;
+
+/// File types recognised from their leading "magic" bytes
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+    Bmp,
+    Tiff,
+    Avif,
+    Heic,
+    Mp4,
+    WebM,
+    Mkv,
+    Mov,
+}
+
+impl FileKind {
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        use FileKind::*;
+
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Some(Png);
+        }
+        if bytes.starts_with(b"\xff\xd8\xff") {
+            return Some(Jpeg);
+        }
+        // GIF87a cannot be animated, GIF89a may or may not be
+        if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            return Some(Gif);
+        }
+        if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            return Some(WebP);
+        }
+        // "BM" alone starts plenty of text, so also the size of a known DIB header
+        if bytes.starts_with(b"BM")
+            && let Some(header) = bytes.get(14..18)
+            && matches!(
+                u32::from_le_bytes(header.try_into().unwrap()),
+                12 | 40 | 52 | 56 | 64 | 108 | 124
+            )
+        {
+            return Some(Bmp);
+        }
+        if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            return Some(Tiff);
+        }
+        if bytes.starts_with(b"\x1a\x45\xdf\xa3") {
+            // WebM is Matroska with a different doc type
+            return if contains(bytes, b"webm") {
+                Some(WebM)
+            } else {
+                contains(bytes, b"matroska").then_some(Mkv)
+            };
+        }
+        if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+            return match &bytes[8..12] {
+                b"qt  " => Some(Mov),
+                b"avif" | b"avis" => Some(Avif),
+                b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => {
+                    Some(Heic)
+                }
+                b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1"
+                | b"M4V " | b"M4VH" | b"M4VP" | b"dash" | b"MSNV" => Some(Mp4),
+                _ => None,
+            };
+        }
+        // Old QuickTime files may not start with a `ftyp` atom
+        if bytes.len() >= 8 && matches!(&bytes[4..8], b"moov" | b"mdat" | b"wide" | b"free") {
+            return Some(Mov);
+        }
+        None
+    }
+
+    pub fn detect(path: &Path) -> Result<Self> {
+        let path_str = path.display();
+        let mut bytes = Vec::with_capacity(SNIFF_LEN);
+        fs::File::open(path)
+            .wrap_err(format!("Could not open {path_str} to detect its type"))?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut bytes)
+            .wrap_err(format!("Could not read {path_str} to detect its type"))?;
+        Self::sniff(&bytes).ok_or_else(|| Error::Unsupported {
+            message: format!(
+                "Unsupported file type for {path_str}, expected PNG, JPEG, GIF, WebP, BMP, TIFF, AVIF, HEIC, MP4, WebM, MKV or MOV"
+            ),
+        })
+    }
+
+    pub fn is_video(self) -> bool {
+        use FileKind::*;
+
+        matches!(self, Mp4 | WebM | Mkv | Mov)
+    }
+
+    // Images the upload endpoints take without conversion
+    pub fn is_accepted(self) -> bool {
+        use FileKind::*;
+
+        matches!(self, Png | Jpeg | Gif)
+    }
+
+    // What Gyazo calls the "type" of a file
+    pub fn extension(self) -> &'static str {
+        use FileKind::*;
+
+        match self {
+            Png => "png",
+            Jpeg => "jpg",
+            Gif => "gif",
+            WebP => "webp",
+            Bmp => "bmp",
+            Tiff => "tiff",
+            Avif => "avif",
+            Heic => "heic",
+            Mp4 => "mp4",
+            WebM => "webm",
+            Mkv => "mkv",
+            Mov => "mov",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        use FileKind::*;
+
+        match self {
+            Png => "image/png",
+            Jpeg => "image/jpeg",
+            Gif => "image/gif",
+            WebP => "image/webp",
+            Bmp => "image/bmp",
+            Tiff => "image/tiff",
+            Avif => "image/avif",
+            Heic => "image/heic",
+            Mp4 => "video/mp4",
+            WebM => "video/webm",
+            Mkv => "video/x-matroska",
+            Mov => "video/quicktime",
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for FileKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use FileKind::*;
+
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "png" => Png,
+            "jpg" | "jpeg" => Jpeg,
+            "gif" => Gif,
+            "webp" => WebP,
+            "bmp" => Bmp,
+            "tif" | "tiff" => Tiff,
+            "avif" => Avif,
+            "heic" | "heif" => Heic,
+            "mp4" => Mp4,
+            "webm" => WebM,
+            "mkv" => Mkv,
+            "mov" => Mov,
+            _ => {
+                return Err(Error::Unsupported {
+                    message: format!("Unknown file type `{s}`"),
+                });
+            }
+        })
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A box of `brand` as it starts MP4, MOV, AVIF and HEIC files
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        [b"\0\0\0\x18ftyp", &brand[..], b"\0\0\0\0isom"].concat()
+    }
+
+    #[test]
+    fn sniff_images() {
+        use FileKind::*;
+
+        assert_eq!(FileKind::sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some(Png));
+        assert_eq!(FileKind::sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some(Jpeg));
+        assert_eq!(FileKind::sniff(b"GIF89a\x01\0\x01\0"), Some(Gif));
+        assert_eq!(FileKind::sniff(b"GIF87a\x01\0\x01\0"), Some(Gif));
+        assert_eq!(FileKind::sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some(WebP));
+        assert_eq!(FileKind::sniff(b"II*\0\x08\0\0\0"), Some(Tiff));
+        assert_eq!(FileKind::sniff(b"MM\0*\0\0\0\x08"), Some(Tiff));
+        assert_eq!(FileKind::sniff(&ftyp(b"avif")), Some(Avif));
+        assert_eq!(FileKind::sniff(&ftyp(b"heic")), Some(Heic));
+        assert_eq!(FileKind::sniff(&ftyp(b"mif1")), Some(Heic));
+    }
+
+    #[test]
+    fn sniff_bmp_only_with_a_known_header_size() {
+        let mut bmp = b"BM\x36\0\0\0\0\0\0\0\x36\0\0\0".to_vec();
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        assert_eq!(FileKind::sniff(&bmp), Some(FileKind::Bmp));
+        assert_eq!(FileKind::sniff(b"BMW is a car maker, not an image"), None);
+        assert_eq!(FileKind::sniff(b"BM"), None);
+    }
+
+    #[test]
+    fn sniff_videos() {
+        use FileKind::*;
+
+        assert_eq!(FileKind::sniff(&ftyp(b"isom")), Some(Mp4));
+        assert_eq!(FileKind::sniff(&ftyp(b"mp42")), Some(Mp4));
+        assert_eq!(FileKind::sniff(&ftyp(b"qt  ")), Some(Mov));
+        assert_eq!(FileKind::sniff(b"\0\0\0\x08wide\0\0\0\0mdat"), Some(Mov));
+        let ebml = b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84";
+        assert_eq!(FileKind::sniff(&[&ebml[..], b"webm"].concat()), Some(WebM));
+        assert_eq!(
+            FileKind::sniff(&[&ebml[..], b"matroska"].concat()),
+            Some(Mkv)
+        );
+        assert_eq!(FileKind::sniff(&ebml[..]), None);
+    }
+
+    #[test]
+    fn sniff_rejects_unknown_and_short_input() {
+        assert_eq!(FileKind::sniff(b""), None);
+        assert_eq!(FileKind::sniff(b"%PDF-1.7"), None);
+        assert_eq!(FileKind::sniff(&ftyp(b"crx ")), None);
+        assert_eq!(FileKind::sniff(b"RIFF\x24\0\0\0WAVE"), None);
+        assert_eq!(FileKind::sniff(b"\0\0\0\x18ftyp"), None);
+    }
+
+    #[test]
+    fn extensions_parse_back() {
+        use FileKind::*;
+
+        for kind in [
+            Png, Jpeg, Gif, WebP, Bmp, Tiff, Avif, Heic, Mp4, WebM, Mkv, Mov,
+        ] {
+            assert_eq!(kind.extension().parse::<FileKind>().unwrap(), kind);
+            assert_eq!(kind.is_video(), kind.mime().starts_with("video/"));
+        }
+        assert!("exe".parse::<FileKind>().is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest as _, Md5};
+
+use crate::{Config, ContentId, Result, Uploaded, WrapErr as _, jsonl::JsonlStore};
+
+// Gyazo IDs are the MD5 hash of the uploaded file
+pub fn content_id(path: &Path) -> Result<ContentId> {
+    let error = || format!("Could not read {} to hash it", path.display());
+    let mut file = BufReader::new(fs::File::open(path).wrap_err(error())?);
+    let mut md5 = Md5::new();
+    io::copy(&mut file, &mut md5).wrap_err(error())?;
+    Ok(ContentId::from_digest(&md5.finalize()))
+}
+
+/// Past uploads from this machine, by ID and by the hash of the
+/// file they were made from, which differs for converted files
+#[derive(Default)]
+pub struct Ledger {
+    entries: HashMap<String, Uploaded>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Line {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(flatten)]
+    uploaded: Uploaded,
+}
+
+impl Ledger {
+    pub fn path() -> PathBuf {
+        let mut path = Config::dir();
+        path.push("gyazo-uploads.jsonl");
+        path
+    }
+
+    fn store() -> JsonlStore<Line> {
+        JsonlStore::new(Self::path(), "upload ledger")
+    }
+
+    pub fn load() -> Result<Self> {
+        let mut entries = HashMap::new();
+        for line in Self::store().load()? {
+            if let Some(source) = line.source {
+                entries.insert(source, line.uploaded.clone());
+            }
+            entries.insert(line.uploaded.id.clone(), line.uploaded);
+        }
+        Ok(Self { entries })
+    }
+
+    // By Gyazo ID or source hash
+    pub fn get(&self, id: &str) -> Option<&Uploaded> {
+        self.entries.get(id)
+    }
+
+    // Under the hash of the `source` file as well, if it was converted
+    pub fn record(uploaded: &Uploaded, source: &str) -> Result<()> {
+        let line = Line {
+            source: (source != uploaded.id).then(|| source.to_string()),
+            uploaded: uploaded.clone(),
+        };
+        Self::store().append(&[line])
+    }
+}
@@ -3,9 +3,13 @@
 //! Note that the URLs are always set to the 16 byte MD5 hash
 //! of the image or video.
 
-use std::{io, path::Path};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use futures_util::Stream;
+use futures_util::{Stream, StreamExt as _, TryStreamExt as _};
 use http::HeaderMap;
 pub use http::StatusCode;
 #[macro_use(Deserialize, Serialize)]
@@ -14,12 +18,49 @@ use serde_json::{Value, json};
 use thiserror::Error;
 pub use url::Url;
 
+mod backup;
+pub use backup::Backup;
+mod browser;
+pub use browser::browser_url;
+mod capture;
 #[cfg(feature = "cli")]
 pub mod cli;
+pub use capture::{
+    CaptureBackend, CaptureMode, Captured, GnomeScreenshot, Grim, Import, Maim, Scrot, Session,
+    Spectacle, capture_backend, capture_backends,
+};
+mod clipboard;
+pub use clipboard::{CopyTarget, clipboard_image, copy_to_clipboard, copy_upload};
+mod command;
 mod config;
 pub use config::Config;
+mod convert;
+pub use convert::{Prepared, convert_image, prepare, transcode_video};
+mod embed;
+pub use embed::embed_metadata;
+mod export;
+pub use export::{ArchiveFormat, Export};
+mod id;
+pub use id::{ContentId, ImageId};
 mod image;
+mod jsonl;
 pub use image::{File, Image};
+mod kind;
+pub use kind::FileKind;
+mod ledger;
+pub use ledger::{Ledger, content_id};
+mod mp4;
+use mp4::Mp4Cache;
+mod notify;
+pub use notify::Notifier;
+mod queue;
+pub use queue::{Queue, Queued};
+mod record;
+pub use record::{Portal, RecordBackend, WfRecorder, X11Grab, record_backend, record_backends};
+mod template;
+pub use template::NameTemplate;
+mod window;
+pub use window::{ActiveWindow, Region, active_window};
 // mod gui;
 // pub use gui::Gui;
 
@@ -33,16 +74,18 @@ pub struct Client {
     cookie: Option<String>,
     device: Option<String>,
     key: Option<String>,
+    // Clones share one connection pool
+    http: reqwest::Client,
 }
 
 pub const API_URL: &str = "https://api.gyazo.com/api";
 pub const API_IMAGE_UPLOAD_URL: &str = "https://upload.gyazo.com/api/upload";
 pub const CGI_IMAGE_UPLOAD_URL: &str = "https://upload.gyazo.com/upload.cgi";
 pub const VIDEO_UPLOAD_URL: &str = "https://gif.gyazo.com/gif/upload";
+pub const PERMALINK_URL: &str = "https://gyazo.com";
 
 // Maybe "Uploaded with Gyoza: <url>"?
-// And can override this (to use some detected app instead of an ad)
-// in the config.
+// Captures use the detected app instead, see `active_window`.
 pub const DEFAULT_APP: &str = "https://github.com/brooke-eva/gyazo";
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
@@ -55,17 +98,44 @@ pub enum Error {
     #[error("{message}")]
     Missing { message: String },
     #[error("{message}")]
+    Unsupported { message: String },
+    #[error("{message}")]
+    InvalidId { message: String },
+    #[error("{message}")]
+    InvalidTemplate { message: String },
+    #[error("{message}")]
     Request {
         message: String,
         source: reqwest::Error,
     },
     #[error("{message}")]
     Io { message: String, source: io::Error },
+    #[error("{message} ({status}): {stderr}")]
+    Command {
+        message: String,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+    #[error("{message}")]
+    Image {
+        message: String,
+        source: ::image::ImageError,
+    },
     #[error("{message}")]
     Url {
         message: String,
         source: url::ParseError,
     },
+    #[error("{message}")]
+    Zip {
+        message: String,
+        source: zip::result::ZipError,
+    },
+    #[error("{message}")]
+    DBus {
+        message: String,
+        source: Box<zbus::Error>,
+    },
     #[error("{message} ({text})")]
     Api {
         message: String,
@@ -81,6 +151,20 @@ pub enum Error {
     },
 }
 
+impl Error {
+    // Failures that may go away by retrying later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Request { source, .. } => !source.is_builder() && !source.is_decode(),
+            Error::Api { status, .. } => {
+                matches!(status, ApiStatus::RateLimited | ApiStatus::Unexpected)
+                    || matches!(status, ApiStatus::Undocumented(code) if code.is_server_error())
+            }
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ApiStatus {
     #[error("invalid request")]
@@ -149,7 +233,6 @@ impl Verify for reqwest::Response {
         if status.is_success() {
             return Ok(self);
         }
-        println!("status: {status:?}");
         let status = match status.as_u16() {
             400 => InvalidRequest,
             401 => Unauthenticated,
@@ -165,7 +248,6 @@ impl Verify for reqwest::Response {
             .text()
             .await
             .unwrap_or_else(|_| "TEXT MISSING".to_string());
-        println!("{text}");
         Err(Error::Api {
             message: msg.to_string(),
             status,
@@ -230,6 +312,51 @@ impl<T> WrapErr<T, reqwest::Error> for reqwest::Result<T> {
     }
 }
 
+impl<T> WrapErr<T, ::image::ImageError> for ::image::ImageResult<T> {
+    fn wrap_err<D>(self, msg: D) -> Result<T>
+    where
+        D: core::fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Ok(t) => Ok(t),
+            Err(source) => Err(Error::Image {
+                message: msg.to_string(),
+                source,
+            }),
+        }
+    }
+}
+
+impl<T> WrapErr<T, zip::result::ZipError> for zip::result::ZipResult<T> {
+    fn wrap_err<D>(self, msg: D) -> Result<T>
+    where
+        D: core::fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Ok(t) => Ok(t),
+            Err(source) => Err(Error::Zip {
+                message: msg.to_string(),
+                source,
+            }),
+        }
+    }
+}
+
+impl<T> WrapErr<T, zbus::Error> for zbus::Result<T> {
+    fn wrap_err<D>(self, msg: D) -> Result<T>
+    where
+        D: core::fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Ok(t) => Ok(t),
+            Err(source) => Err(Error::DBus {
+                message: msg.to_string(),
+                source: Box::new(source),
+            }),
+        }
+    }
+}
+
 impl WrapErr<Url, url::ParseError> for core::result::Result<Url, url::ParseError> {
     fn wrap_err<D>(self, msg: D) -> Result<Url>
     where
@@ -303,6 +430,7 @@ impl Client {
             cookie: config.cookie.clone(),
             device: config.device.clone(),
             key: config.key.clone(),
+            http: reqwest::Client::new(),
         }
     }
 
@@ -326,7 +454,8 @@ impl Client {
     where
         T: serde::de::DeserializeOwned + TypeName,
     {
-        let response = reqwest::Client::new()
+        let response = self
+            .http
             .get(url)
             .query(&[("access_token", self.expect_key()?)])
             .query(query)
@@ -355,7 +484,7 @@ impl Client {
     where
         T: serde::de::DeserializeOwned + TypeName,
     {
-        reqwest::Client::new()
+        self.http
             .get(url)
             .header("cookie", format!("Gyazo_session={}", self.expect_cookie()?))
             .query(query)
@@ -368,10 +497,196 @@ impl Client {
             .await
     }
 
-    pub async fn get(&self, image_id: &str) -> Result<File> {
+    pub async fn get(&self, image_id: &ImageId) -> Result<File> {
         let url = &format!("{API_URL}/images/{image_id}");
 
-        Ok(self.api_get::<Image>(url, &[]).await?.into_file().await)
+        let mut image = self.api_get::<Image>(url, &[]).await?;
+        let mut cache = Mp4Cache::load().unwrap_or_default();
+        self.fix_mp4(core::slice::from_mut(&mut image), &mut cache, 1)
+            .await;
+        Ok(image.into_file())
+    }
+
+    // The public API does not expose whether a "gif" is actually
+    // an "mp4" or not, only whether its MP4 download exists does.
+    // Checks up to `jobs` at a time, remembering the answers.
+    async fn fix_mp4(&self, images: &mut [Image], cache: &mut Mp4Cache, jobs: usize) {
+        let unknown: Vec<String> = images
+            .iter()
+            .filter(|image| image.file_type == "gif" && cache.get(&image.image_id).is_none())
+            .map(|image| image.image_id.clone())
+            .collect();
+        let results: Vec<(String, Result<bool>)> = futures_util::stream::iter(unknown)
+            .map(|id| async move {
+                let mp4 = image::mp4_exists(&self.http, &id).await;
+                (id, mp4)
+            })
+            .buffer_unordered(jobs.max(1))
+            .collect()
+            .await;
+        // Failed checks leave the image a "gif" and are asked again next time
+        let mut checked = Vec::new();
+        for (id, result) in results {
+            match result {
+                Ok(mp4) => checked.push((id, mp4)),
+                Err(error) => eprintln!("Warning: {error}"),
+            }
+        }
+        // Only a speedup, so failing to write it is not an error
+        cache.record(&checked).ok();
+
+        for image in images {
+            if image.file_type == "gif" && cache.get(&image.image_id) == Some(true) {
+                image.file_type = "mp4".to_string();
+            }
+        }
+    }
+
+    // Streams the file into `<path>.part`, resuming from whatever an
+    // earlier attempt left there, and renames it to `path` once it
+    // is complete. Returns the size of the file.
+    pub async fn download(&self, file: &File, path: &Path, overwrite: bool) -> Result<u64> {
+        use std::io::Write as _;
+
+        let path_str = path.display().to_string();
+        let url = file.download.as_str();
+        if !overwrite && path.exists() {
+            return Err(Error::Io {
+                message: format!("Could not create new file {path_str}"),
+                source: io::ErrorKind::AlreadyExists.into(),
+            });
+        }
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let part_str = part.display().to_string();
+
+        let mut offset = part.metadata().map(|meta| meta.len()).unwrap_or(0);
+        let mut request = self.http.get(url);
+        if offset > 0 {
+            request = request.header(http::header::RANGE, format!("bytes={offset}-"));
+        }
+        let mut response = request
+            .send()
+            .await
+            .wrap_err("Could not connect to file download URL")?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // Nothing left past the partial file, or it is not ours, so start over
+            offset = 0;
+            response = self
+                .http
+                .get(url)
+                .send()
+                .await
+                .wrap_err("Could not connect to file download URL")?;
+        }
+        let mut response = response.verify(format!("Download of {url} failed")).await?;
+
+        // The server may ignore the range and send everything, or send
+        // another range than asked for, which cannot be appended
+        let range = content_range(response.headers())
+            .filter(|_| response.status() == StatusCode::PARTIAL_CONTENT);
+        let resumed = offset > 0 && range.is_some_and(|(start, _)| start == offset);
+        if !resumed && response.status() == StatusCode::PARTIAL_CONTENT {
+            response = self
+                .http
+                .get(url)
+                .send()
+                .await
+                .wrap_err("Could not connect to file download URL")?
+                .verify(format!("Download of {url} failed"))
+                .await?;
+            if response.status() == StatusCode::PARTIAL_CONTENT {
+                return Err(Error::String(format!(
+                    "Download of {url} sent part of the file when asked for all of it"
+                )));
+            }
+        }
+        let expected = if resumed {
+            range.and_then(|(_, total)| total)
+        } else {
+            offset = 0;
+            response.content_length()
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("Could not create directory for {path_str}"))?;
+        }
+        let mut out = std::fs::OpenOptions::new();
+        if resumed {
+            out.append(true);
+        } else {
+            out.write(true).truncate(true);
+        }
+        let mut out = out
+            .create(true)
+            .open(&part)
+            .wrap_err(format!("Could not open {part_str}"))?;
+        let mut size = offset;
+        let mut byte_stream = response.bytes_stream();
+        while let Some(bytes) = byte_stream.next().await {
+            let bytes = bytes.wrap_err(format!(
+                "Could not read bytes from file download URL, run again to resume from {part_str}"
+            ))?;
+            out.write_all(&bytes)
+                .wrap_err(format!("Could not write to {part_str}"))?;
+            size += bytes.len() as u64;
+        }
+        out.sync_all()
+            .wrap_err(format!("Could not flush {part_str} to disk"))?;
+
+        if let Some(expected) = expected
+            && size != expected
+        {
+            return Err(Error::String(format!(
+                "Download of {url} ended after {size} of {expected} bytes, run again to resume from {part_str}"
+            )));
+        }
+        std::fs::rename(&part, path)
+            .wrap_err(format!("Could not move {part_str} to {path_str}"))?;
+        Ok(size)
+    }
+
+    // Streams the file into a writer such as stdout, which cannot
+    // be resumed. Returns the size of the file.
+    pub async fn download_to(&self, file: &File, out: &mut (impl io::Write + Send)) -> Result<u64> {
+        self.fetch_to(file.download.as_str(), out).await
+    }
+
+    pub(crate) async fn fetch_to(
+        &self,
+        url: &str,
+        out: &mut (impl io::Write + Send),
+    ) -> Result<u64> {
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .wrap_err("Could not connect to file download URL")?
+            .verify(format!("Download of {url} failed"))
+            .await?;
+        let expected = response.content_length();
+
+        let mut size = 0;
+        let mut byte_stream = response.bytes_stream();
+        while let Some(bytes) = byte_stream.next().await {
+            let bytes = bytes.wrap_err("Could not read bytes from file download URL")?;
+            out.write_all(&bytes)
+                .wrap_err("Could not write downloaded bytes")?;
+            size += bytes.len() as u64;
+        }
+        out.flush().wrap_err("Could not flush downloaded bytes")?;
+
+        if let Some(expected) = expected
+            && size != expected
+        {
+            return Err(Error::String(format!(
+                "Download of {url} ended after {size} of {expected} bytes"
+            )));
+        }
+        Ok(size)
     }
 
     pub async fn count(&self) -> Result<usize> {
@@ -408,19 +723,26 @@ impl Client {
 
     // To get a Result<Vec<File>>, use futures::TryStreamExt::try_collect
     pub async fn list(&self) -> impl Stream<Item = Result<File>> {
+        self.list_with(ListOptions::default()).await
+    }
+
+    pub async fn list_with(&self, options: ListOptions) -> impl Stream<Item = Result<File>> {
         async_stream::try_stream! {
+            let mut cache = options.detect_mp4.then(|| Mp4Cache::load().unwrap_or_default());
             let mut page_number = 1;
             let mut received = 0;
             loop {
                 let url = &format!("{API_URL}/images");
                 let page_number_string = page_number.to_string();
                 let query = &[("page", page_number_string.as_str()), ("per_page", "100")];
-                let (page, headers): (Vec<Image>, _) = self.api_get_with_headers(url, query).await?;
+                let (mut page, headers): (Vec<Image>, _) = self.api_get_with_headers(url, query).await?;
                 received += page.len();
 
-                for mut image in page.into_iter() {
-                    image.fix_mp4().await;
-                    yield image.into_file().await;
+                if let Some(cache) = cache.as_mut() {
+                    self.fix_mp4(&mut page, cache, options.jobs).await;
+                }
+                for image in page.into_iter() {
+                    yield image.into_file();
                 }
 
                 let count: usize = headers.get("x-total-count").unwrap().to_str().unwrap().parse().unwrap();
@@ -453,14 +775,131 @@ impl Client {
         }
     }
 
-    pub async fn upload_image(&self, path: &Path, upload: &Upload) -> Result<Url> {
+    // Routes by the detected file type, not the extension
+    pub async fn upload(&self, path: &Path, upload: &Upload) -> Result<Uploaded> {
+        let ledger = if upload.dedup {
+            Ledger::load()?
+        } else {
+            Ledger::default()
+        };
+        self.upload_with(path, upload, &ledger).await
+    }
+
+    async fn upload_with(&self, path: &Path, upload: &Upload, ledger: &Ledger) -> Result<Uploaded> {
+        // Converting and hashing would hold up other uploads
+        let source = {
+            let path = path.to_path_buf();
+            blocking(move || content_id(&path)).await?
+        };
+        // Before converting, which may take a while
+        if upload.dedup
+            && let Some(uploaded) = ledger.get(source.as_str())
+        {
+            return Ok(Uploaded {
+                existing: true,
+                ..uploaded.clone()
+            });
+        }
+        let prepared = {
+            let (path, upload) = (path.to_path_buf(), upload.clone());
+            blocking(move || prepare(&path, &upload)).await?
+        };
+        let path = prepared.path();
+        if upload.dedup {
+            let id = if prepared.is_converted() {
+                let path = path.to_path_buf();
+                blocking(move || content_id(&path)).await?
+            } else {
+                source.clone()
+            };
+            if let Some(uploaded) = self
+                .find_uploaded(id.as_str(), path, prepared.kind, ledger)
+                .await?
+            {
+                return Ok(uploaded);
+            }
+        }
+        let uploaded = if prepared.kind.is_video() {
+            self.upload_video(path, upload).await?
+        } else {
+            self.upload_image(path, upload).await?
+        };
+        // The upload itself succeeded, at worst it is sent again next time
+        Ledger::record(&uploaded, source.as_str()).ok();
+        Ok(uploaded)
+    }
+
+    // Looks for an earlier upload of `path` as `id`, first in the
+    // local ledger and then by its predicted direct URL
+    async fn find_uploaded(
+        &self,
+        id: &str,
+        path: &Path,
+        kind: FileKind,
+        ledger: &Ledger,
+    ) -> Result<Option<Uploaded>> {
+        if let Some(uploaded) = ledger.get(id) {
+            return Ok(Some(Uploaded {
+                existing: true,
+                ..uploaded.clone()
+            }));
+        }
+
+        let exists = if kind == FileKind::Mp4 {
+            image::mp4_exists(&self.http, id).await.unwrap_or(false)
+        } else {
+            self.http
+                .head(image::download_url(id, kind.extension()))
+                .send()
+                .await
+                .is_ok_and(|response| response.status().is_success())
+        };
+        if !exists {
+            return Ok(None);
+        }
+        let permalink = Url::parse(&format!("{PERMALINK_URL}/{id}"))
+            .wrap_err("Could not construct permalink for existing upload")?;
+        let mut uploaded =
+            Uploaded::new(permalink, kind.extension().to_string(), file_size(path)?)?;
+        uploaded.existing = true;
+        Ok(Some(uploaded))
+    }
+
+    // Uploads up to `jobs` files at a time, yielding each result as it
+    // finishes, so one failure does not stop the others
+    pub fn upload_many<'a>(
+        &'a self,
+        paths: impl IntoIterator<Item = PathBuf> + 'a,
+        upload: &'a Upload,
+        jobs: usize,
+    ) -> impl Stream<Item = (PathBuf, Result<Uploaded>)> + 'a {
+        // Read once for all of them
+        let ledger = Arc::new(if upload.dedup {
+            Ledger::load()
+        } else {
+            Ok(Ledger::default())
+        });
+        futures_util::stream::iter(paths)
+            .map(move |path| {
+                let ledger = ledger.clone();
+                async move {
+                    let result = match ledger.as_ref() {
+                        Ok(ledger) => self.upload_with(&path, upload, ledger).await,
+                        Err(error) => Err(Error::String(error.to_string())),
+                    };
+                    (path, result)
+                }
+            })
+            .buffer_unordered(jobs.max(1))
+    }
+
+    pub async fn upload_image(&self, path: &Path, upload: &Upload) -> Result<Uploaded> {
+        let converted = convert_image(path, upload.convert_to)?;
+        let path = converted.as_ref().map_or(path, |file| file.path());
         // if self.id.is_some() {
-        self.upload_image_cgi(path, upload)
-            .await
-            .map(|(url, _)| url)
+        self.upload_image_cgi(path, upload).await
         // } else if self.key.is_some() {
-        //     let (image, _) = self.upload_image_api(path, upload).await?;
-        //     Ok(image.permalink_url)
+        //     self.upload_image_api(path, upload).await
         // } else {
         //     panic!("Need access token or device ID");
         // }
@@ -468,32 +907,39 @@ impl Client {
 
     // allowed types: jpg, png, gif
     // mp4: pro/teams user only
-    pub async fn upload_image_cgi(&self, path: &Path, upload: &Upload) -> Result<(Url, Device)> {
+    pub async fn upload_image_cgi(&self, path: &Path, upload: &Upload) -> Result<Uploaded> {
+        let kind = FileKind::detect(path)?;
         let device = if upload.anonymous {
             None
         } else {
             self.device.clone()
         };
-        let form = reqwest::multipart::Form::new()
+        let mut metadata = json!({
+            "app": upload.app.as_str(),
+            // no obvious effect
+            // "note": ...,
+        });
+        // Left out when unknown, rather than sent as null
+        if let Some(title) = upload.title.as_deref() {
+            // ends up in "Source"
+            metadata["title"] = title.into();
+        }
+        if let Some(url) = upload.url.as_deref() {
+            metadata["url"] = url.into();
+        }
+        let mut form = reqwest::multipart::Form::new()
             .text("id", device.clone().unwrap_or_default())
-            .text(
-                "metadata",
-                json!({
-                    "app": upload.app.as_str(),
-                    // ends up in "Source"
-                    // "title": ...,
-                    // no obvious effect
-                    // "url": ...,
-                    // no obvious effect
-                    // "note": ...,
-                })
-                .to_string(),
-            )
-            .file("imagedata", path)
-            .await
-            .wrap_err("Could not prepare image upload form")?;
+            .text("metadata", metadata.to_string())
+            .part(
+                "imagedata",
+                file_part(path, kind, upload.progress.clone()).await?,
+            );
+        if let Some(created_at) = upload.created_at {
+            form = form.text("created_at", created_at.to_string());
+        }
 
-        let response = reqwest::Client::new()
+        let response = self
+            .http
             .post(CGI_IMAGE_UPLOAD_URL)
             .multipart(form)
             // .header("User-Agent", "Gyazo/1.3.2")
@@ -506,51 +952,60 @@ impl Client {
             .await?;
 
         let headers = response.headers();
-        let maybe_token = headers
+        let token = headers
             .get("x-gyazo-session-token")
-            .map(|token| token.to_str().unwrap().to_string());
+            .and_then(|token| token.to_str().ok())
+            .map(str::to_string);
         // If we didn't send device ID... expect to receive one
-
-        let device = device.unwrap_or_else(|| {
-            headers
+        let device = match device {
+            Some(device) => device,
+            None => headers
                 .get("x-gyazo-id")
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string()
-        });
+                .and_then(|id| id.to_str().ok())
+                .wrap_none("CGI image upload response did not assign a device ID")?
+                .to_string(),
+        };
 
-        let mut url = response
+        let permalink = response
             .text()
             .await
             .wrap_err("CGI image upload response did not contain text")?
             .parse::<Url>()
             .wrap_err("CGI image upload response did not contain a URL")?;
 
-        if let Some(token) = maybe_token {
-            url.set_query(Some(&format!("token={token}")));
-        }
-        Ok((url, device))
+        let file_type = kind.extension().to_string();
+        let mut uploaded = Uploaded::new(permalink, file_type, file_size(path)?)?;
+        uploaded.token = token;
+        uploaded.device = Some(device);
+        Ok(uploaded)
     }
 
     // allowed types: jpg, png, gif
     // mp4: pro/teams user only
-    pub async fn upload_image_api(&self, path: &Path, upload: &Upload) -> Result<File> {
+    pub async fn upload_image_api(&self, path: &Path, upload: &Upload) -> Result<Uploaded> {
         // let access_policy = if upload.public_access { "anyone" } else { "only_me" };
+        let kind = FileKind::detect(path)?;
         let public_metadata = upload.public_metadata.to_string();
 
-        let query = &[
+        let mut query = vec![
             ("app", upload.app.as_str()),
             // ("access_policy", access_policy),
             ("metadata_is_public", public_metadata.as_str()),
         ];
+        if let Some(title) = upload.title.as_deref() {
+            query.push(("title", title));
+        }
+        if let Some(url) = upload.url.as_deref() {
+            query.push(("referer_url", url));
+        }
 
-        let form = reqwest::multipart::Form::new()
-            .file("imagedata", path)
-            .await
-            .wrap_err("Could not prepare image upload form")?;
+        let form = reqwest::multipart::Form::new().part(
+            "imagedata",
+            file_part(path, kind, upload.progress.clone()).await?,
+        );
 
-        let mut request = reqwest::Client::new()
+        let mut request = self
+            .http
             .post(API_IMAGE_UPLOAD_URL)
             .multipart(form)
             // .header("User-Agent", "Gyazo/1.3.2")
@@ -558,11 +1013,13 @@ impl Client {
             .query(&[("access_token", self.expect_key()?)])
             .query(&query);
 
-        if let Some(created) = path.metadata().ok().and_then(|meta| meta.created().ok()) {
-            let created_at = created
-                .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs_f32();
+        let created_at = upload.created_at.or_else(|| {
+            path.metadata()
+                .ok()
+                .and_then(|meta| meta.created().ok())
+                .map(queue::unix_seconds)
+        });
+        if let Some(created_at) = created_at {
             // This shows as "Uploaded at"
             request = request.query(&[("created_at", created_at)]);
         };
@@ -576,17 +1033,35 @@ impl Client {
             .extract_json("Could not decode image API upload response as JSON")
             .await?;
 
-        Ok(image.into_file().await)
+        let download = Url::parse(&image.download_url())
+            .wrap_err("API image upload response did not lead to a download URL")?;
+        Ok(Uploaded {
+            id: image.image_id,
+            permalink: image.permalink_url,
+            download,
+            token: None,
+            device: None,
+            file_type: image.file_type,
+            size: file_size(path)?,
+            existing: false,
+        })
     }
 
-    pub async fn upload_video(&self, path: &Path) -> Result<Url> {
-        let form = reqwest::multipart::Form::new()
-            .text("id", self.expect_device()?.to_string())
-            .file("data", path)
-            .await
-            .wrap_err("Could not prepare video upload form")?;
+    pub async fn upload_video(&self, path: &Path, upload: &Upload) -> Result<Uploaded> {
+        let kind = FileKind::detect(path)?;
+        let device = self.expect_device()?.to_string();
+        let mut form = reqwest::multipart::Form::new()
+            .text("id", device.clone())
+            .part(
+                "data",
+                file_part(path, kind, upload.progress.clone()).await?,
+            );
+        if let Some(created_at) = upload.created_at {
+            form = form.text("created_at", created_at.to_string());
+        }
 
-        let url = reqwest::Client::new()
+        let permalink = self
+            .http
             .post(VIDEO_UPLOAD_URL)
             .multipart(form)
             .send()
@@ -596,17 +1071,119 @@ impl Client {
             .await?
             .text()
             .await
-            .wrap_err("Video API upload response did not contain text")?;
+            .wrap_err("Video API upload response did not contain text")?
+            .parse::<Url>()
+            .wrap_err("Video API upload response did not contain a URL")?;
 
-        Url::parse(&url).wrap_err("Video API upload response did not contain a URL")
+        let mut uploaded = Uploaded::new(permalink, "mp4".to_string(), file_size(path)?)?;
+        uploaded.device = Some(device);
+        Ok(uploaded)
     }
 }
 
+// Upload progress as bytes sent out of the total
+pub type Progress = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Named and typed by content, so renamed or extensionless files
+// are not rejected or mislabeled by the upload endpoints.
+// Streamed from disk, reporting each chunk as it is taken for sending.
+async fn file_part(
+    path: &Path,
+    kind: FileKind,
+    progress: Option<Progress>,
+) -> Result<reqwest::multipart::Part> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .wrap_err(format!("Could not read {} for upload", path.display()))?;
+    let total = file
+        .metadata()
+        .await
+        .wrap_err(format!("Could not determine size of {}", path.display()))?
+        .len();
+    let mut sent = 0;
+    let stream =
+        tokio_util::io::ReaderStream::with_capacity(file, CHUNK_SIZE).inspect_ok(move |chunk| {
+            sent += chunk.len() as u64;
+            if let Some(progress) = progress.as_ref() {
+                progress(sent, total);
+            }
+        });
+    reqwest::multipart::Part::stream_with_length(reqwest::Body::wrap_stream(stream), total)
+        .file_name(format!("gyazo.{}", kind.extension()))
+        .mime_str(kind.mime())
+        .wrap_err("Could not set MIME type for upload")
+}
+
+// The start and total from eg. "bytes 100-199/200", where the total may be "*"
+fn content_range(headers: &HeaderMap) -> Option<(u64, Option<u64>)> {
+    let (range, total) = headers
+        .get(http::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('/')?;
+    let start = range.split_once('-')?.0.trim().parse().ok()?;
+    Some((start, total.trim().parse().ok()))
+}
+
+// Runs `f` on a thread for blocking work, such as reading whole files
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|error| Error::String(format!("Background task failed: {error}")))?
+}
+
+pub(crate) fn file_size(path: &Path) -> Result<u64> {
+    Ok(path
+        .metadata()
+        .wrap_err(format!("Could not determine size of {}", path.display()))?
+        .len())
+}
+
+/// How `Client::list_with` lists files
+#[derive(Clone, Debug)]
+pub struct ListOptions {
+    /// Check which "gif" files are really MP4 videos, which costs a
+    /// request for each one not checked before. Otherwise they stay "gif".
+    pub detect_mp4: bool,
+    /// How many of those checks to run at once
+    pub jobs: usize,
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        Self {
+            detect_mp4: true,
+            jobs: 8,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Upload {
     pub app: String,
+    /// Title of the captured window or page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Address of the captured page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
     // pub public_access: bool,
     pub public_metadata: bool,
     pub anonymous: bool,
+    /// What to convert images to when Gyazo does not accept them
+    pub convert_to: FileKind,
+    /// Whether to transcode videos other than MP4 before uploading
+    pub transcode: bool,
+    #[serde(skip)]
+    pub progress: Option<Progress>,
+    /// Whether to skip files that were uploaded before
+    pub dedup: bool,
+    /// When the file was captured, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<f64>,
 }
 
 impl Upload {
@@ -620,9 +1197,70 @@ impl Default for Upload {
         let config = Config::load();
         Self {
             app: DEFAULT_APP.to_string(),
+            title: None,
+            url: None,
             // public_access: config.upload.public_access,
             public_metadata: config.upload.public_metadata,
             anonymous: false,
+            convert_to: config.upload.convert_to,
+            transcode: true,
+            progress: None,
+            dedup: config.upload.dedup,
+            created_at: None,
+        }
+    }
+}
+
+/// The result of an upload, by whichever method
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Uploaded {
+    pub id: String,
+    pub permalink: Url,
+    pub download: Url,
+    /// Session token, letting the uploader view the file without logging in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// The device ID used, or assigned for anonymous uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub size: u64,
+    /// Whether this was found uploaded before, rather than sent now
+    #[serde(default)]
+    pub existing: bool,
+}
+
+impl Uploaded {
+    fn new(permalink: Url, file_type: String, size: u64) -> Result<Self> {
+        let id = permalink
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|id| !id.is_empty())
+            .wrap_none(format!(
+                "Upload permalink `{permalink}` did not contain an ID"
+            ))?
+            .to_string();
+        let download = Url::parse(&image::download_url(&id, &file_type))
+            .wrap_err("Could not construct download URL for upload")?;
+        Ok(Self {
+            id,
+            permalink,
+            download,
+            token: None,
+            device: None,
+            file_type,
+            size,
+            existing: false,
+        })
+    }
+
+    /// The permalink, with the session token if there is one
+    pub fn url(&self) -> Url {
+        let mut url = self.permalink.clone();
+        if let Some(token) = self.token.as_deref() {
+            url.set_query(Some(&format!("token={token}")));
         }
+        url
     }
 }
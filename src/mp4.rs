@@ -0,0 +1,55 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{Result, jsonl::JsonlStore};
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    id: String,
+    mp4: bool,
+}
+
+/// Whether "gif" images are really MP4 videos, by ID, as checked
+/// before. An ID never changes type, so entries do not expire.
+#[derive(Default)]
+pub(crate) struct Mp4Cache {
+    entries: HashMap<String, bool>,
+}
+
+impl Mp4Cache {
+    pub(crate) fn path() -> PathBuf {
+        let mut path = dirs::cache_dir().unwrap_or_else(std::env::temp_dir);
+        path.push("gyazo-mp4.jsonl");
+        path
+    }
+
+    fn store() -> JsonlStore<Entry> {
+        JsonlStore::new(Self::path(), "MP4 cache")
+    }
+
+    pub(crate) fn load() -> Result<Self> {
+        let entries = Self::store()
+            .load()?
+            .into_iter()
+            .map(|entry| (entry.id, entry.mp4))
+            .collect();
+        Ok(Self { entries })
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<bool> {
+        self.entries.get(id).copied()
+    }
+
+    pub(crate) fn record(&mut self, results: &[(String, bool)]) -> Result<()> {
+        let entries: Vec<Entry> = results
+            .iter()
+            .map(|(id, mp4)| {
+                self.entries.insert(id.clone(), *mp4);
+                Entry {
+                    id: id.clone(),
+                    mp4: *mp4,
+                }
+            })
+            .collect();
+        Self::store().append(&entries)
+    }
+}
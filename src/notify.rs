@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use zbus::{
+    MatchRule,
+    blocking::{Connection, MessageIterator, Proxy},
+    message::Type,
+    zvariant::Value,
+};
+
+use crate::{FileKind, Result, Url, WrapErr as _};
+
+const NOTIFICATIONS: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+/// Desktop notifications, sent to the freedesktop notification service over D-Bus
+pub struct Notifier {
+    connection: Connection,
+    proxy: Proxy<'static>,
+    capabilities: Vec<String>,
+    timeout: Duration,
+    opener: Option<String>,
+}
+
+enum Event {
+    Action(u32, String),
+    Closed(u32),
+}
+
+impl Notifier {
+    // On the session bus from `DBUS_SESSION_BUS_ADDRESS`, showing
+    // notifications for `timeout` where the server lets us choose
+    pub fn new(timeout: Duration) -> Result<Self> {
+        let connection = Connection::session().wrap_err("Could not connect to the session bus")?;
+        Self::with_connection(connection, timeout)
+    }
+
+    pub fn with_connection(connection: Connection, timeout: Duration) -> Result<Self> {
+        let proxy = Proxy::new(
+            &connection,
+            NOTIFICATIONS,
+            NOTIFICATIONS_PATH,
+            NOTIFICATIONS,
+        )
+        .wrap_err("Could not reach the notification service")?;
+        let capabilities = proxy
+            .call("GetCapabilities", &())
+            .wrap_err("Could not reach the notification service")?;
+        Ok(Self {
+            connection,
+            proxy,
+            capabilities,
+            timeout,
+            opener: None,
+        })
+    }
+
+    // Opens clicked links with `program` rather than the default browser
+    pub fn with_opener(mut self, program: impl Into<String>) -> Self {
+        self.opener = Some(program.into());
+        self
+    }
+
+    // Shows the link with a thumbnail of `path` if it is an image, and opens
+    // the link when clicked, waiting for that until the notification closes
+    pub fn uploaded(&self, url: &Url, path: &Path) -> Result<()> {
+        let mut hints = HashMap::from([("category", Value::from("transfer.complete"))]);
+        if let Some(thumbnail) = thumbnail(path) {
+            hints.insert("image-data", thumbnail);
+        }
+        let clickable = self.has("actions");
+        let actions: &[&str] = if clickable { &["default", "Open"] } else { &[] };
+        // Listening first, so no click comes before it
+        let events = if clickable {
+            Some(self.events()?)
+        } else {
+            None
+        };
+        let id = self.notify(
+            "Uploaded to Gyazo",
+            url.as_str(),
+            actions,
+            hints,
+            self.timeout,
+        )?;
+
+        let Some(events) = events else {
+            return Ok(());
+        };
+        // Some servers keep notifications around without closing them
+        let deadline = Instant::now() + self.timeout;
+        while let Ok(event) =
+            events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            match event {
+                Event::Action(event_id, action) if event_id == id && action == "default" => {
+                    return match &self.opener {
+                        Some(program) => open::with(url.as_str(), program),
+                        None => open::that(url.as_str()),
+                    }
+                    .wrap_err("Could not open URL in browser");
+                }
+                Event::Closed(event_id) if event_id == id => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Critical and without a timeout, so it stays until dismissed
+    pub fn error(&self, message: &str) -> Result<()> {
+        let hints = HashMap::from([("urgency", Value::from(2u8))]);
+        self.notify("Gyazo failed", message, &[], hints, Duration::ZERO)
+            .map(drop)
+    }
+
+    fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|has| has == capability)
+    }
+
+    fn notify(
+        &self,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value>,
+        // Zero for never
+        timeout: Duration,
+    ) -> Result<u32> {
+        let body = if self.has("body-markup") {
+            body.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        } else {
+            body.to_string()
+        };
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        self.proxy
+            .call(
+                "Notify",
+                &("Gyazo", 0u32, "", summary, body, actions, hints, timeout),
+            )
+            .wrap_err("Could not send a notification")
+    }
+
+    // Clicks and closes of our notifications, from a thread
+    // as blocking iterators cannot time out
+    fn events(&self) -> Result<mpsc::Receiver<Event>> {
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .interface(NOTIFICATIONS)
+            .and_then(|rule| rule.path(NOTIFICATIONS_PATH))
+            .map(|rule| rule.build())
+            .wrap_err("Could not listen to the notification service")?;
+        let messages = MessageIterator::for_match_rule(rule, &self.connection, None)
+            .wrap_err("Could not listen to the notification service")?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for message in messages.flatten() {
+                let header = message.header();
+                let body = message.body();
+                let event = match header.member().map(|member| member.as_str()) {
+                    Some("ActionInvoked") => body
+                        .deserialize()
+                        .ok()
+                        .map(|(id, action)| Event::Action(id, action)),
+                    Some("NotificationClosed") => body
+                        .deserialize::<(u32, u32)>()
+                        .ok()
+                        .map(|(id, _reason)| Event::Closed(id)),
+                    _ => None,
+                };
+                if let Some(event) = event
+                    && sender.send(event).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
+
+// As raw pixels for the `image-data` hint, since servers may only
+// read an `image-path` once the temporary capture is gone
+fn thumbnail(path: &Path) -> Option<Value<'static>> {
+    if FileKind::detect(path).ok()?.is_video() {
+        return None;
+    }
+    let mut image = image::open(path).ok()?;
+    if image.width() > 256 || image.height() > 256 {
+        image = image.thumbnail(256, 256);
+    }
+    let image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+    Some(Value::from((
+        width as i32,
+        height as i32,
+        width as i32 * 4,
+        true,
+        8i32,
+        4i32,
+        image.into_raw(),
+    )))
+}
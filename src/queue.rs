@@ -0,0 +1,126 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{Config, ContentId, Error, Result, Upload, WrapErr as _, content_id};
+
+/// An upload that failed and was kept to be retried
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Queued {
+    /// The content ID of the file, also naming it in the queue
+    pub id: ContentId,
+    pub file: PathBuf,
+    pub upload: Upload,
+    /// Seconds since the epoch
+    pub queued_at: u64,
+    pub error: String,
+}
+
+pub struct Queue;
+
+impl Queue {
+    pub fn dir() -> PathBuf {
+        let mut path = Config::dir();
+        path.push("gyazo-queue");
+        path
+    }
+
+    // Copies the file into the queue, keeping its modification time
+    // as the capture time unless the upload already has one
+    pub fn push(path: &Path, upload: &Upload, error: &Error) -> Result<Queued> {
+        let dir = Self::dir();
+        fs::create_dir_all(&dir).wrap_err("Could not create upload queue directory")?;
+
+        let id = content_id(path)?;
+        let mut file = dir.join(id.as_str());
+        if let Some(extension) = path.extension() {
+            file.set_extension(extension);
+        }
+        fs::copy(path, &file).wrap_err(format!("Could not copy {} into queue", path.display()))?;
+
+        let mut upload = upload.clone();
+        if upload.created_at.is_none() {
+            upload.created_at = path
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .map(unix_seconds);
+        }
+        let queued = Queued {
+            id,
+            file,
+            upload,
+            queued_at: unix_seconds(SystemTime::now()) as u64,
+            error: error.to_string(),
+        };
+        fs::write(
+            queued.entry_path(),
+            serde_json::to_string_pretty(&queued).unwrap(),
+        )
+        .wrap_err("Could not write upload queue entry")?;
+        Ok(queued)
+    }
+
+    // Oldest first
+    pub fn list() -> Result<Vec<Queued>> {
+        let entries = match fs::read_dir(Self::dir()) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error).wrap_err("Could not read upload queue directory"),
+        };
+        let mut queue = Vec::new();
+        for entry in entries {
+            let path = entry
+                .wrap_err("Could not read upload queue directory")?
+                .path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .wrap_err(format!("Could not read queue entry {}", path.display()))?;
+            let queued: Queued = serde_json::from_str(&contents).map_err(|source| Error::Json {
+                message: format!("Could not decode queue entry {}", path.display()),
+                text: contents,
+                source,
+                type_name: "Queued",
+            })?;
+            queue.push(queued);
+        }
+        queue.sort_by_key(|queued| queued.queued_at);
+        Ok(queue)
+    }
+}
+
+impl Queued {
+    fn entry_path(&self) -> PathBuf {
+        Queue::dir().join(format!("{}.json", self.id))
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        fs::remove_file(&self.file)
+            .or_else(ignore_missing)
+            .wrap_err(format!(
+                "Could not remove queued file {}",
+                self.file.display()
+            ))?;
+        fs::remove_file(self.entry_path())
+            .or_else(ignore_missing)
+            .wrap_err(format!("Could not remove queue entry {}", self.id))
+    }
+}
+
+fn ignore_missing(error: io::Error) -> io::Result<()> {
+    if error.kind() == io::ErrorKind::NotFound {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+pub(crate) fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
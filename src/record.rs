@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    env, io,
+    os::fd::OwnedFd,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::Pid,
+};
+use tempfile::NamedTempFile;
+use zbus::{
+    blocking::{Connection, Proxy},
+    zvariant::{self, ObjectPath, OwnedValue, Value},
+};
+
+use crate::{
+    Captured, Error, FileKind, Region, Result, Session, WrapErr as _,
+    capture::{Backend, find_backend},
+    command,
+};
+
+/// An external screen recorder
+pub trait RecordBackend: Send + Sync {
+    /// As set in the `capture.recorder` config
+    fn name(&self) -> &'static str;
+
+    /// The executables it runs
+    fn programs(&self) -> &'static [&'static str];
+
+    fn supports(&self, session: Session) -> bool;
+
+    /// Records what the user selects into `path` as MP4 for `duration`
+    fn record_to(&self, path: &Path, duration: Duration) -> Result<()>;
+
+    fn is_available(&self, session: Session) -> bool {
+        self.supports(session)
+            && self
+                .programs()
+                .iter()
+                .all(|program| command::exists(program))
+    }
+
+    fn record(&self, duration: Duration) -> Result<Captured> {
+        let file = NamedTempFile::with_suffix(".mp4")
+            .wrap_err("Could not create a temporary file for the recording")?;
+        self.record_to(file.path(), duration)?;
+        let size = crate::file_size(file.path())?;
+        if size == 0 {
+            return Err(Error::Missing {
+                message: format!("Nothing was recorded with {}", self.name()),
+            });
+        }
+        let kind = FileKind::detect(file.path())?;
+        if kind != FileKind::Mp4 {
+            return Err(Error::Unsupported {
+                message: format!("{} wrote a {kind} file instead of MP4", self.name()),
+            });
+        }
+        Ok(Captured { file, size })
+    }
+}
+
+// H.264 in 4:2:0 needs even dimensions
+fn even(region: Region) -> Region {
+    Region {
+        width: (region.width / 2 * 2).max(2),
+        height: (region.height / 2 * 2).max(2),
+        ..region
+    }
+}
+
+// `slop` and `slurp` print the selection in the format asked for
+fn select(command: &mut Command) -> Result<Region> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command::run(command, format!("Could not select a region with {program}"))?;
+    let output = String::from_utf8_lossy(&output);
+    let region = output.trim();
+    if region.is_empty() {
+        return Err(Error::Missing {
+            message: "No region was selected".to_string(),
+        });
+    }
+    region.parse().map(even)
+}
+
+// For recorders that run until interrupted, as with Ctrl+C
+fn record_for(
+    backend: &dyn RecordBackend,
+    command: &mut Command,
+    duration: Duration,
+) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut recorder = Recorder(command::spawn(
+        command.stdout(Stdio::null()),
+        format!("Could not record a video with {}", backend.name()),
+    )?);
+    thread::sleep(duration);
+    let pid = Pid::from_raw(recorder.0.child.id() as i32);
+    kill(pid, Signal::SIGINT)
+        .map_err(io::Error::from)
+        .wrap_err(format!("Could not stop `{program}`"))?;
+    recorder.0.wait()
+}
+
+// Killed if still running when dropped, so returning
+// early does not leave it recording in the background
+struct Recorder(command::Spawned);
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let child = &mut self.0.child;
+        if let Ok(None) = child.try_wait() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+/// FFmpeg's `x11grab` with the region from `slop`, and audio from PulseAudio
+pub struct X11Grab;
+
+impl RecordBackend for X11Grab {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["ffmpeg", "slop"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        session != Session::Wayland
+    }
+
+    fn record_to(&self, path: &Path, duration: Duration) -> Result<()> {
+        let region = select(Command::new("slop").args(["-f", "%x,%y,%wx%h"]))?;
+        let display = env::var("DISPLAY").unwrap_or_else(|_| ":0.0".to_string());
+        let mut ffmpeg = Command::new("ffmpeg");
+        ffmpeg
+            .args(["-f", "x11grab", "-framerate", "24", "-video_size"])
+            .arg(format!("{}x{}", region.width, region.height))
+            .arg("-i")
+            .arg(format!("{display}+{},{}", region.x, region.y))
+            .args(["-f", "alsa", "-i", "pulse", "-t"])
+            .arg(duration.as_secs_f64().to_string())
+            // Without this, videos do not play on Windows
+            .args(["-pix_fmt", "yuv420p"])
+            // Otherwise it warns of too few frames to estimate the rate
+            .args(["-probesize", "64MB", "-y"])
+            .arg(path);
+        command::run(
+            &mut ffmpeg,
+            format!("Could not record a video with {}", self.name()),
+        )
+        .map(drop)
+    }
+}
+
+/// `wf-recorder` with the region from `slurp`, for wlroots compositors such as Sway
+pub struct WfRecorder;
+
+impl RecordBackend for WfRecorder {
+    fn name(&self) -> &'static str {
+        "wf-recorder"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["wf-recorder", "slurp"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        crate::capture::wlroots(session)
+    }
+
+    fn record_to(&self, path: &Path, duration: Duration) -> Result<()> {
+        let Region {
+            x,
+            y,
+            width,
+            height,
+        } = select(Command::new("slurp").args(["-f", "%x,%y,%wx%h"]))?;
+        let mut wf_recorder = Command::new("wf-recorder");
+        wf_recorder
+            .arg("--audio")
+            .arg("-g")
+            .arg(format!("{x},{y} {width}x{height}"))
+            .args(["-c", "libx264", "-x", "yuv420p", "-y", "-f"])
+            .arg(path);
+        record_for(self, &mut wf_recorder, duration)
+    }
+}
+
+/// The desktop portal's screencast, recorded from PipeWire with GStreamer,
+/// for Wayland desktops such as GNOME and KDE
+///
+/// The portal asks which screen or window to record, rather than a region.
+/// It needs the GStreamer plugins for PipeWire, x264 and MP4.
+pub struct Portal;
+
+const PORTAL: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SCREEN_CAST: &str = "org.freedesktop.portal.ScreenCast";
+
+impl RecordBackend for Portal {
+    fn name(&self) -> &'static str {
+        "portal"
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        &["gst-launch-1.0"]
+    }
+
+    fn supports(&self, session: Session) -> bool {
+        session == Session::Wayland
+    }
+
+    fn record_to(&self, path: &Path, duration: Duration) -> Result<()> {
+        let cast = screen_cast()?;
+        let mut caps = "video/x-raw,format=I420,framerate=24/1".to_string();
+        if let Some((width, height)) = cast.size {
+            caps.push_str(&format!(
+                ",width={},height={}",
+                width / 2 * 2,
+                height / 2 * 2
+            ));
+        }
+        let mut gst_launch = Command::new("gst-launch-1.0");
+        // The PipeWire remote is handed over as stdin
+        gst_launch
+            .stdin(Stdio::from(cast.remote))
+            .args(["-e", "pipewiresrc", "fd=0"])
+            .arg(format!("path={}", cast.node))
+            .args(["do-timestamp=true", "keepalive-time=1000", "!"])
+            .args(["videoconvert", "!", "videoscale", "!", "videorate", "!"])
+            .arg(caps)
+            .args([
+                "!",
+                "x264enc",
+                "speed-preset=veryfast",
+                "!",
+                "h264parse",
+                "!",
+            ])
+            .args(["mp4mux", "!", "filesink"])
+            .arg(format!("location={}", path.display()));
+        // The portal session ends with the connection
+        let result = record_for(self, &mut gst_launch, duration);
+        drop(cast.connection);
+        result
+    }
+}
+
+struct ScreenCast {
+    connection: Connection,
+    /// The PipeWire remote to read the stream from
+    remote: OwnedFd,
+    node: u32,
+    size: Option<(i32, i32)>,
+}
+
+fn unexpected(what: &str) -> Error {
+    Error::String(format!(
+        "Unexpected reply from the screencast portal: {what}"
+    ))
+}
+
+// Asks the portal for a stream of a screen or window
+fn screen_cast() -> Result<ScreenCast> {
+    let connection = Connection::session().wrap_err("Could not connect to the session bus")?;
+    let portal = Proxy::new(&connection, PORTAL, PORTAL_PATH, SCREEN_CAST)
+        .wrap_err("Could not reach the screencast portal")?;
+
+    let results = portal_request(&connection, &portal, "CreateSession", |token| {
+        (HashMap::from([
+            ("handle_token", token.clone()),
+            ("session_handle_token", token),
+        ]),)
+    })?;
+    // A string, though older portals sent an object path
+    let session = match results.get("session_handle").map(|value| &**value) {
+        Some(Value::Str(handle)) => handle.to_string(),
+        Some(Value::ObjectPath(handle)) => handle.to_string(),
+        _ => return Err(unexpected("no session handle")),
+    };
+    let session =
+        ObjectPath::try_from(session.as_str()).map_err(|_| unexpected("invalid session handle"))?;
+
+    // Monitors and windows, with the cursor drawn in if possible
+    let source_types = portal
+        .get_property::<u32>("AvailableSourceTypes")
+        .unwrap_or(1)
+        & 0b11;
+    let cursor_modes = portal
+        .get_property::<u32>("AvailableCursorModes")
+        .unwrap_or(0);
+    portal_request(&connection, &portal, "SelectSources", |token| {
+        let mut options = HashMap::from([
+            ("handle_token", token),
+            ("types", Value::from(source_types)),
+            ("multiple", Value::from(false)),
+        ]);
+        if cursor_modes & 0b10 != 0 {
+            options.insert("cursor_mode", Value::from(0b10u32));
+        }
+        (&session, options)
+    })?;
+
+    let results = portal_request(&connection, &portal, "Start", |token| {
+        (&session, "", HashMap::from([("handle_token", token)]))
+    })?;
+    let streams = results
+        .get("streams")
+        .ok_or_else(|| unexpected("no streams"))?;
+    let Value::Array(streams) = &**streams else {
+        return Err(unexpected("streams are not an array"));
+    };
+    let Some(Value::Structure(stream)) = streams.first() else {
+        return Err(Error::Missing {
+            message: "Nothing was selected to record".to_string(),
+        });
+    };
+    let (Some(Value::U32(node)), properties) = (stream.fields().first(), stream.fields().get(1))
+    else {
+        return Err(unexpected("stream has no node"));
+    };
+    let size = match properties {
+        Some(Value::Dict(properties)) => properties.get::<&str, (i32, i32)>(&"size").ok().flatten(),
+        _ => None,
+    };
+
+    let remote: zvariant::OwnedFd = portal
+        .call(
+            "OpenPipeWireRemote",
+            &(&session, HashMap::<&str, Value>::new()),
+        )
+        .wrap_err("Could not open the screencast's PipeWire remote")?;
+    Ok(ScreenCast {
+        connection,
+        remote: remote.into(),
+        node: *node,
+        size,
+    })
+}
+
+// Portal methods answer through the `Response` signal of a request
+// object, whose path is known up front from the token we pick
+fn portal_request<B>(
+    connection: &Connection,
+    portal: &Proxy,
+    method: &'static str,
+    body: impl FnOnce(Value<'static>) -> B,
+) -> Result<HashMap<String, OwnedValue>>
+where
+    B: serde::Serialize + zvariant::DynamicType,
+{
+    let token = format!("gyazo_{}_{method}", std::process::id());
+    let sender = connection
+        .unique_name()
+        .ok_or_else(|| unexpected("no bus name"))?
+        .trim_start_matches(':')
+        .replace('.', "_");
+    let request = Proxy::new(
+        connection,
+        PORTAL,
+        format!("{PORTAL_PATH}/request/{sender}/{token}"),
+        "org.freedesktop.portal.Request",
+    )
+    .wrap_err("Could not reach the screencast portal")?;
+    let mut responses = request
+        .receive_signal("Response")
+        .wrap_err("Could not listen to the screencast portal")?;
+    portal
+        .call_method(method, &body(Value::from(token)))
+        .wrap_err(format!("The screencast portal failed to {method}"))?;
+    let response = responses.next().ok_or_else(|| unexpected("no response"))?;
+    let (code, results): (u32, HashMap<String, OwnedValue>) =
+        response.body().deserialize().wrap_err(format!(
+            "Could not decode the screencast portal's {method} response"
+        ))?;
+    match code {
+        0 => Ok(results),
+        1 => Err(Error::Missing {
+            message: "Recording was cancelled".to_string(),
+        }),
+        _ => Err(Error::String(format!(
+            "The screencast portal failed to {method}"
+        ))),
+    }
+}
+
+// In order of preference when detecting
+pub fn record_backends() -> Vec<Box<dyn RecordBackend>> {
+    vec![Box::new(WfRecorder), Box::new(X11Grab), Box::new(Portal)]
+}
+
+// The named backend, or the first one available in this session
+pub fn record_backend(name: Option<&str>) -> Result<Box<dyn RecordBackend>> {
+    find_backend(record_backends(), name, "record", "screen recorder")
+}
+
+impl Backend for dyn RecordBackend {
+    fn name(&self) -> &'static str {
+        RecordBackend::name(self)
+    }
+
+    fn programs(&self) -> &'static [&'static str] {
+        RecordBackend::programs(self)
+    }
+
+    fn is_available(&self, session: Session) -> bool {
+        RecordBackend::is_available(self, session)
+    }
+}
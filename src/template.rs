@@ -0,0 +1,344 @@
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{Error, File, Result};
+
+// Long titles would otherwise run into file name length limits,
+// which count bytes rather than characters
+const MAX_VALUE_BYTES: usize = 100;
+
+/// A file name with placeholders filled in from a `File`
+///
+/// Supports `{id}`, `{type}`, `{app}`, `{title}` and `{create_time}`,
+/// the latter optionally with a strftime format as in
+/// `{create_time:%Y-%m-%d}`. Use `{{` and `}}` for literal braces.
+/// Literal `/` in the template makes directories, but placeholder
+/// values are sanitized so they can only ever name a single file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    Id,
+    Type,
+    App,
+    Title,
+    CreateTime(Option<String>),
+}
+
+impl NameTemplate {
+    pub const DEFAULT: &str = "{id}.{type}";
+
+    pub fn render(&self, file: &File) -> Result<PathBuf> {
+        let mut name = String::new();
+        for part in &self.parts {
+            let meta = file.meta.as_ref();
+            let value = match part {
+                Part::Literal(literal) => {
+                    name.push_str(literal);
+                    continue;
+                }
+                Part::Id => file.id.clone(),
+                Part::Type => file.file_type.clone(),
+                Part::App => meta.and_then(|meta| meta.app.clone()).unwrap_or_default(),
+                Part::Title => meta.and_then(|meta| meta.title.clone()).unwrap_or_default(),
+                Part::CreateTime(None) => file.create_time.clone(),
+                Part::CreateTime(Some(format)) => format_time(&file.create_time, format)?,
+            };
+            name.push_str(&sanitize(&value));
+        }
+
+        let path = PathBuf::from(name);
+        let safe = path.components().next().is_some()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !safe {
+            return Err(Error::InvalidTemplate {
+                message: format!(
+                    "Name template `{}` gave `{}` for {}, which is not a relative path",
+                    self.template,
+                    path.display(),
+                    file.id
+                ),
+            });
+        }
+        Ok(path)
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::DEFAULT.parse().unwrap()
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidTemplate {
+            message: format!("Invalid name template `{template}`: {reason}"),
+        };
+        if Path::new(template).is_absolute() {
+            return Err(invalid("must be a relative path"));
+        }
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(invalid("unmatched `}`")),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(invalid("unclosed `{`")),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(core::mem::take(&mut literal)));
+                    }
+                    let (key, format) = match placeholder.split_once(':') {
+                        Some((key, format)) => (key, Some(format.to_string())),
+                        None => (placeholder.as_str(), None),
+                    };
+                    parts.push(match (key, format) {
+                        ("create_time", Some(format)) if !is_time_format(&format) => {
+                            return Err(invalid(&format!("invalid time format `{format}`")));
+                        }
+                        ("create_time", format) => Part::CreateTime(format),
+                        (_, Some(_)) => {
+                            return Err(invalid(&format!("`{{{key}}}` does not take a format")));
+                        }
+                        ("id", None) => Part::Id,
+                        ("type", None) => Part::Type,
+                        ("app", None) => Part::App,
+                        ("title", None) => Part::Title,
+                        _ => return Err(invalid(&format!("unknown placeholder `{{{key}}}`"))),
+                    });
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self {
+            template: template.to_string(),
+            parts,
+        })
+    }
+}
+
+impl TryFrom<String> for NameTemplate {
+    type Error = Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        template.parse()
+    }
+}
+
+impl From<NameTemplate> for String {
+    fn from(template: NameTemplate) -> Self {
+        template.template
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+// In the local time zone, as that is what file browsers show
+pub(crate) fn format_time(timestamp: &str, format: &str) -> Result<String> {
+    let timestamp: jiff::Timestamp = timestamp.parse().map_err(|_| Error::InvalidTemplate {
+        message: format!("Could not parse creation time `{timestamp}`"),
+    })?;
+    let zoned = timestamp.to_zoned(jiff::tz::TimeZone::system());
+    jiff::fmt::strtime::format(format, &zoned).map_err(|_| Error::InvalidTemplate {
+        message: format!("Invalid time format `{format}`"),
+    })
+}
+
+// Whether `format` formats any time, checked up front so that bad
+// templates fail before downloading anything rather than on each file
+fn is_time_format(format: &str) -> bool {
+    let time = jiff::Timestamp::UNIX_EPOCH.to_zoned(jiff::tz::TimeZone::UTC);
+    jiff::fmt::strtime::format(format, &time).is_ok()
+}
+
+// `name`, or failing that with `-{id}` and then a counter before its
+// extension, whichever is not yet `taken`
+pub(crate) fn unique_name(name: &str, id: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(name) {
+        return name.to_string();
+    }
+    let file_start = name.rfind('/').map_or(0, |slash| slash + 1);
+    let (stem, extension) = match name[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(file_start + dot),
+        _ => (name, ""),
+    };
+    let with_id = format!("{stem}-{id}{extension}");
+    if !taken(&with_id) {
+        return with_id;
+    }
+    (2..)
+        .map(|n| format!("{stem}-{id}-{n}{extension}"))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+// Makes a value safe as (part of) a single file name
+fn sanitize(value: &str) -> String {
+    let mut value: String = value
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if value.len() > MAX_VALUE_BYTES {
+        let end = (0..=MAX_VALUE_BYTES)
+            .rev()
+            .find(|&end| value.is_char_boundary(end))
+            .unwrap_or(0);
+        value.truncate(end);
+    }
+    // No hidden files, `.` or `..`
+    let value = value.trim().trim_start_matches('.');
+    if value.is_empty() {
+        "_".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(title: &str) -> File {
+        serde_json::from_value(serde_json::json!({
+            "id": "0123456789abcdef0123456789abcdef",
+            "type": "png",
+            "create_time": "2024-05-06T07:08:09Z",
+            "download": "https://i.gyazo.com/0123456789abcdef0123456789abcdef.png",
+            "permalink": "https://gyazo.com/0123456789abcdef0123456789abcdef",
+            "meta": { "app": "Firefox", "title": title },
+        }))
+        .unwrap()
+    }
+
+    fn render(template: &str, title: &str) -> PathBuf {
+        template
+            .parse::<NameTemplate>()
+            .unwrap()
+            .render(&file(title))
+            .unwrap()
+    }
+
+    #[test]
+    fn sanitize_keeps_values_to_one_file() {
+        assert_eq!(sanitize("a/b\\c\nd"), "a_b_c_d");
+        assert_eq!(sanitize("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize("."), "_");
+        assert_eq!(sanitize(".hidden"), "hidden");
+        assert_eq!(sanitize("  "), "_");
+    }
+
+    #[test]
+    fn sanitize_truncates_to_max_bytes_on_char_boundary() {
+        assert_eq!(sanitize(&"a".repeat(150)).len(), MAX_VALUE_BYTES);
+        // Two bytes each, so 100 bytes is exactly 50 of them
+        assert_eq!(sanitize(&"é".repeat(60)), "é".repeat(50));
+        // Three bytes each, so only 99 bytes fit
+        assert_eq!(sanitize(&"あ".repeat(40)), "あ".repeat(33));
+    }
+
+    #[test]
+    fn render_fills_placeholders() {
+        assert_eq!(
+            render(NameTemplate::DEFAULT, ""),
+            Path::new("0123456789abcdef0123456789abcdef.png")
+        );
+        assert_eq!(
+            render("{app}/{create_time:%Y}/{title}.{type}", "Hello"),
+            Path::new("Firefox/2024/Hello.png")
+        );
+        assert_eq!(
+            render("{{id}}-{id}", ""),
+            Path::new("{id}-0123456789abcdef0123456789abcdef")
+        );
+    }
+
+    #[test]
+    fn render_never_escapes_the_directory() {
+        for title in ["..", "../..", "/etc/passwd", ".", "", "../x/../y"] {
+            let path = render("{title}", title);
+            assert_eq!(path.components().count(), 1, "{title} gave {path:?}");
+            assert!(
+                matches!(path.components().next(), Some(Component::Normal(_))),
+                "{title} gave {path:?}"
+            );
+        }
+        let error = "../{id}".parse::<NameTemplate>().unwrap().render(&file(""));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn parse_rejects_bad_templates() {
+        for template in [
+            "/tmp/{id}",
+            "{id",
+            "id}",
+            "{nope}",
+            "{id:%Y}",
+            "{create_time:%!}",
+            "{create_time:%}",
+        ] {
+            assert!(
+                template.parse::<NameTemplate>().is_err(),
+                "{template} was accepted"
+            );
+        }
+        assert!(
+            "{create_time:%Y-%m-%d %H.%M.%S}"
+                .parse::<NameTemplate>()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn unique_name_adds_id_then_counter() {
+        let taken = ["a.png", "a-id.png", "dir.d/b"];
+        let taken = |name: &str| taken.contains(&name);
+        assert_eq!(unique_name("new.png", "id", taken), "new.png");
+        assert_eq!(unique_name("a.png", "id", taken), "a-id-2.png");
+        assert_eq!(unique_name("dir.d/b", "id", taken), "dir.d/b-id");
+    }
+}
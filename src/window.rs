@@ -0,0 +1,325 @@
+use std::{fmt, process::Command, str::FromStr};
+
+use serde_json::Value;
+
+use crate::{Error, Result, Session, command};
+
+/// A rectangle of the screen, in pixels from its top left corner
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    // As X11 tools take it, `WxH+X+Y`
+    pub fn geometry(&self) -> String {
+        let Region {
+            x,
+            y,
+            width,
+            height,
+        } = self;
+        format!("{width}x{height}{x:+}{y:+}")
+    }
+}
+
+// Parsed from `x,y,wxh`
+impl FromStr for Region {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::String(format!("Invalid region `{s}`, expected x,y,wxh"));
+        let mut parts = s.split(',').map(str::trim);
+        let (Some(x), Some(y), Some(size), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let region = Region {
+            x: x.parse().map_err(|_| invalid())?,
+            y: y.parse().map_err(|_| invalid())?,
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+        };
+        if region.width == 0 || region.height == 0 {
+            return Err(invalid());
+        }
+        Ok(region)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Region {
+            x,
+            y,
+            width,
+            height,
+        } = self;
+        write!(f, "{x},{y},{width}x{height}")
+    }
+}
+
+/// The focused window, as far as it could be told
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ActiveWindow {
+    /// Application name, such as its X11 class or Wayland app ID
+    pub app: Option<String>,
+    pub title: Option<String>,
+}
+
+// Asks X11 through `xprop`, or the compositor on Sway and Hyprland.
+// GNOME and KDE on Wayland offer no way to ask.
+pub fn active_window(session: Session) -> Result<ActiveWindow> {
+    let window = if session != Session::Wayland {
+        x11_active_window()?
+    } else if command::exists("swaymsg") {
+        let tree = json(Command::new("swaymsg").args(["-t", "get_tree"]), "swaymsg")?;
+        let node = focused_node(&tree).ok_or_else(no_focus)?;
+        ActiveWindow {
+            app: string(&node["app_id"]).or_else(|| string(&node["window_properties"]["class"])),
+            title: string(&node["name"]),
+        }
+    } else if command::exists("hyprctl") {
+        let window = json(
+            Command::new("hyprctl").args(["activewindow", "-j"]),
+            "hyprctl",
+        )?;
+        ActiveWindow {
+            app: string(&window["class"]),
+            title: string(&window["title"]),
+        }
+    } else {
+        return Err(Error::Unsupported {
+            message: "Finding the focused window on Wayland needs Sway or Hyprland".to_string(),
+        });
+    };
+    Ok(window)
+}
+
+// Like `gyazo.perl`, from the window's properties,
+// falling back to the name of its process
+fn x11_active_window() -> Result<ActiveWindow> {
+    let id = active_window_id()?;
+    let output = command::run(
+        Command::new("xprop").arg("-id").arg(&id).args([
+            "_NET_WM_NAME",
+            "WM_NAME",
+            "WM_CLASS",
+            "_NET_WM_PID",
+        ]),
+        "Could not read properties of the active window with xprop",
+    )?;
+    let output = String::from_utf8_lossy(&output);
+    // _NET_WM_NAME(UTF8_STRING) = "Title"
+    // WM_CLASS(STRING) = "navigator", "firefox"
+    // _NET_WM_PID(CARDINAL) = 1234
+    let property = |name: &str| {
+        output.lines().find_map(|line| {
+            let (key, value) = line.split_once(" = ")?;
+            (key.split('(').next() == Some(name)).then(|| value.to_string())
+        })
+    };
+    let title = property("_NET_WM_NAME")
+        .or_else(|| property("WM_NAME"))
+        .and_then(|value| quoted(&value).into_iter().next());
+    let class = property("WM_CLASS").and_then(|value| quoted(&value).into_iter().last());
+    let app = class.or_else(|| {
+        let pid = property("_NET_WM_PID")?;
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid.trim())).ok()?;
+        Some(comm.trim().to_string())
+    });
+    Ok(ActiveWindow {
+        app: app.filter(|app| !app.is_empty()),
+        title: title.filter(|title| !title.is_empty()),
+    })
+}
+
+// The strings in an `xprop` value, unescaped
+fn quoted(value: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = value.chars();
+    while chars.by_ref().any(|c| c == '"') {
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => string.extend(chars.next()),
+                c => string.push(c),
+            }
+        }
+        strings.push(string);
+    }
+    strings
+}
+
+fn string(value: &Value) -> Option<String> {
+    value
+        .as_str()
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn no_focus() -> Error {
+    Error::Missing {
+        message: "No window is focused".to_string(),
+    }
+}
+
+// The X11 ID of the focused window, as `xprop` gives it
+pub(crate) fn active_window_id() -> Result<String> {
+    let output = command::run(
+        Command::new("xprop").args(["-root", "_NET_ACTIVE_WINDOW"]),
+        "Could not find the active window with xprop",
+    )?;
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    String::from_utf8_lossy(&output)
+        .split_whitespace()
+        .last()
+        .filter(|id| id.starts_with("0x") && *id != "0x0")
+        .map(str::to_string)
+        .ok_or_else(no_focus)
+}
+
+// The focused window on Wayland, from the compositor
+pub(crate) fn focused_region() -> Result<Region> {
+    if command::exists("swaymsg") {
+        let tree = json(Command::new("swaymsg").args(["-t", "get_tree"]), "swaymsg")?;
+        return focused_node(&tree)
+            .and_then(|node| rect(&node["rect"]))
+            .ok_or_else(no_focus);
+    }
+    if command::exists("hyprctl") {
+        let window = json(
+            Command::new("hyprctl").args(["activewindow", "-j"]),
+            "hyprctl",
+        )?;
+        let number = |value: &Value, i: usize| value.get(i).and_then(Value::as_i64);
+        let (at, size) = (&window["at"], &window["size"]);
+        if let (Some(x), Some(y), Some(width), Some(height)) = (
+            number(at, 0),
+            number(at, 1),
+            number(size, 0),
+            number(size, 1),
+        ) {
+            return Ok(Region {
+                x: x as i32,
+                y: y as i32,
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+        return Err(no_focus());
+    }
+    Err(Error::Unsupported {
+        message: "Finding the focused window on Wayland needs Sway or Hyprland".to_string(),
+    })
+}
+
+// The region of the `n`th monitor, counting from 0 as `xrandr --listmonitors` does
+pub(crate) fn screen_region(n: usize, session: Session) -> Result<Region> {
+    let screens = if session == Session::Wayland {
+        wayland_screens()?
+    } else {
+        x11_screens()?
+    };
+    let count = screens.len();
+    screens.into_iter().nth(n).ok_or_else(|| Error::Missing {
+        message: format!("No screen {n}, there are {count} numbered from 0"),
+    })
+}
+
+fn x11_screens() -> Result<Vec<Region>> {
+    let output = command::run(
+        Command::new("xrandr").arg("--listmonitors"),
+        "Could not list screens with xrandr",
+    )?;
+    // Monitors: 2
+    //  0: +*eDP-1 1920/344x1080/194+0+0  eDP-1
+    //  1: +HDMI-1 2560/597x1440/336+1920+0  HDMI-1
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let geometry = line.split_whitespace().nth(2)?;
+            let (width, rest) = geometry.split_once('x')?;
+            let split = rest.find(['+', '-'])?;
+            let (height, offsets) = rest.split_at(split);
+            // Offsets keep their sign, eg. `+1920-200`
+            let (x, y) = offsets.split_at(offsets[1..].find(['+', '-'])? + 1);
+            Some(Region {
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+                width: width.split('/').next()?.parse().ok()?,
+                height: height.split('/').next()?.parse().ok()?,
+            })
+        })
+        .collect())
+}
+
+fn wayland_screens() -> Result<Vec<Region>> {
+    if command::exists("swaymsg") {
+        let outputs = json(
+            Command::new("swaymsg").args(["-t", "get_outputs"]),
+            "swaymsg",
+        )?;
+        return Ok(outputs
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|output| output["active"].as_bool() != Some(false))
+            .filter_map(|output| rect(&output["rect"]))
+            .collect());
+    }
+    if command::exists("hyprctl") {
+        let monitors = json(Command::new("hyprctl").args(["monitors", "-j"]), "hyprctl")?;
+        return Ok(monitors
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(rect)
+            .collect());
+    }
+    Err(Error::Unsupported {
+        message: "Listing screens on Wayland needs Sway or Hyprland".to_string(),
+    })
+}
+
+fn json(command: &mut Command, tool: &str) -> Result<Value> {
+    let output = command::run(
+        command,
+        format!("Could not query the compositor with {tool}"),
+    )?;
+    let text = String::from_utf8_lossy(&output).into_owned();
+    serde_json::from_str(&text).map_err(|source| Error::Json {
+        message: format!("Could not decode {tool} output"),
+        text,
+        source,
+        type_name: "compositor state",
+    })
+}
+
+fn rect(value: &Value) -> Option<Region> {
+    let number = |key| value.get(key).and_then(Value::as_i64);
+    Some(Region {
+        x: number("x")? as i32,
+        y: number("y")? as i32,
+        width: number("width")? as u32,
+        height: number("height")? as u32,
+    })
+}
+
+fn focused_node(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(focused_node)
+}
@@ -0,0 +1,218 @@
+// Notifications against a mock of the freedesktop notification service,
+// over a peer-to-peer connection so no session bus is needed
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::{fs::PermissionsExt as _, net::UnixStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use gyazo::{Notifier, Url};
+use zbus::{
+    Guid,
+    blocking::{Connection, connection},
+    interface,
+    object_server::SignalEmitter,
+    zvariant::{OwnedValue, Structure},
+};
+
+#[derive(Debug)]
+struct Notification {
+    summary: String,
+    body: String,
+    actions: Vec<String>,
+    hints: HashMap<String, OwnedValue>,
+    expire_timeout: i32,
+}
+
+#[derive(Clone, Copy)]
+enum Reply {
+    Click,
+    Close,
+}
+
+struct Server {
+    notifications: Arc<Mutex<Vec<Notification>>>,
+    reply: Reply,
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl Server {
+    fn get_capabilities(&self) -> Vec<&str> {
+        vec!["actions", "body-markup"]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        _app_name: &str,
+        _replaces_id: u32,
+        _app_icon: &str,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> zbus::fdo::Result<u32> {
+        let id = {
+            let mut notifications = self.notifications.lock().unwrap();
+            notifications.push(Notification {
+                summary,
+                body,
+                actions,
+                hints,
+                expire_timeout,
+            });
+            notifications.len() as u32
+        };
+        // Events of other notifications come first and must be ignored
+        Self::action_invoked(&emitter, id + 1, "default").await?;
+        Self::notification_closed(&emitter, id + 1, 2).await?;
+        match self.reply {
+            Reply::Click => Self::action_invoked(&emitter, id, "default").await?,
+            Reply::Close => Self::notification_closed(&emitter, id, 2).await?,
+        }
+        Ok(id)
+    }
+
+    #[zbus(signal)]
+    async fn action_invoked(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        action_key: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn notification_closed(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        reason: u32,
+    ) -> zbus::Result<()>;
+}
+
+// The server connection has to be kept for as long as it serves
+fn notifier(reply: Reply) -> (Notifier, Arc<Mutex<Vec<Notification>>>, Connection) {
+    let (server_stream, client_stream) = UnixStream::pair().unwrap();
+    let notifications = Arc::new(Mutex::new(Vec::new()));
+    let server = Server {
+        notifications: notifications.clone(),
+        reply,
+    };
+    // Both ends authenticate at once
+    let serving = thread::spawn(move || {
+        connection::Builder::async_io_unix_stream(server_stream)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at("/org/freedesktop/Notifications", server)
+            .unwrap()
+            .build()
+            .unwrap()
+    });
+    let client = connection::Builder::async_io_unix_stream(client_stream)
+        .p2p()
+        .build()
+        .unwrap();
+    let server = serving.join().unwrap();
+    let notifier = Notifier::with_connection(client, Duration::from_secs(10)).unwrap();
+    (notifier, notifications, server)
+}
+
+fn image(width: u32, height: u32) -> tempfile::NamedTempFile {
+    let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+    image::RgbaImage::new(width, height)
+        .save(file.path())
+        .unwrap();
+    file
+}
+
+#[test]
+fn uploaded_sends_link_and_thumbnail() {
+    let (notifier, notifications, _server) = notifier(Reply::Close);
+    let url: Url = "https://gyazo.com/abc?a=1&b=2".parse().unwrap();
+    let file = image(512, 256);
+
+    let started = Instant::now();
+    notifier.uploaded(&url, file.path()).unwrap();
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "did not stop waiting once closed"
+    );
+
+    let notifications = notifications.lock().unwrap();
+    let [notification] = notifications.as_slice() else {
+        panic!("expected one notification, got {notifications:?}");
+    };
+    assert_eq!(notification.summary, "Uploaded to Gyazo");
+    assert_eq!(notification.body, "https://gyazo.com/abc?a=1&amp;b=2");
+    assert_eq!(notification.actions, ["default", "Open"]);
+    assert_eq!(notification.expire_timeout, 10_000);
+    assert_eq!(
+        notification.hints["category"],
+        OwnedValue::try_from(zbus::zvariant::Value::from("transfer.complete")).unwrap()
+    );
+    let image_data: Structure = notification.hints["image-data"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let (width, height, stride, alpha, bits, channels, pixels): (
+        i32,
+        i32,
+        i32,
+        bool,
+        i32,
+        i32,
+        Vec<u8>,
+    ) = image_data.try_into().unwrap();
+    assert_eq!((width, height, stride), (256, 128, 256 * 4));
+    assert_eq!((alpha, bits, channels), (true, 8, 4));
+    assert_eq!(pixels.len(), 256 * 128 * 4);
+}
+
+#[test]
+fn uploaded_opens_link_when_clicked() {
+    let bin = tempfile::tempdir().unwrap();
+    let opened = bin.path().join("opened");
+    let opener = bin.path().join("opener");
+    fs::write(
+        &opener,
+        format!("#!/bin/sh\necho \"$1\" > '{}'\n", opened.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&opener, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (notifier, notifications, _server) = notifier(Reply::Click);
+    let notifier = notifier.with_opener(opener.to_str().unwrap());
+    let url: Url = "https://gyazo.com/abc".parse().unwrap();
+    let file = image(4, 4);
+
+    let started = Instant::now();
+    notifier.uploaded(&url, file.path()).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(notifications.lock().unwrap().len(), 1);
+    assert_eq!(
+        fs::read_to_string(&opened).unwrap().trim(),
+        "https://gyazo.com/abc"
+    );
+}
+
+#[test]
+fn error_stays_until_dismissed() {
+    let (notifier, notifications, _server) = notifier(Reply::Close);
+    notifier.error("No <network>").unwrap();
+
+    let notifications = notifications.lock().unwrap();
+    let [notification] = notifications.as_slice() else {
+        panic!("expected one notification, got {notifications:?}");
+    };
+    assert_eq!(notification.summary, "Gyazo failed");
+    assert_eq!(notification.body, "No &lt;network&gt;");
+    assert!(notification.actions.is_empty());
+    assert_eq!(notification.expire_timeout, 0);
+    assert_eq!(notification.hints["urgency"], OwnedValue::from(2u8));
+}